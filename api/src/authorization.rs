@@ -0,0 +1,38 @@
+use tracing::error;
+use types::user::Role;
+
+use crate::{error::AppError, extractor::AuthUser, AppResult};
+
+// The extractor loads the role from the database on every request,
+// but privileges are never granted to personal access tokens or impersonation sessions.
+fn current_role(auth_user: &AuthUser) -> Role {
+    match auth_user.scopes {
+        Some(_) => Role::User,
+        None => auth_user.role,
+    }
+}
+
+/// Whether the user can edit or delete content they don't own
+pub fn can_moderate(auth_user: &AuthUser) -> bool {
+    current_role(auth_user).can_moderate()
+}
+
+pub fn require_admin(auth_user: &AuthUser) -> AppResult<()> {
+    match current_role(auth_user) {
+        Role::Admin => Ok(()),
+        _ => {
+            error!("User {} is not an admin", auth_user.user_id);
+            Err(AppError::Forbidden)
+        }
+    }
+}
+
+pub fn require_moderator(auth_user: &AuthUser) -> AppResult<()> {
+    match can_moderate(auth_user) {
+        true => Ok(()),
+        false => {
+            error!("User {} is not a moderator", auth_user.user_id);
+            Err(AppError::Forbidden)
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use types::admin::CacheStats;
+
+use crate::{config::CacheConfig, AppState};
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    tick: u64,
+}
+
+// Keeps responses to anonymous reads, which are the same for everyone.
+// Entries expire after the TTL, and the least recently used one makes room
+// once the cache is full. Writes drop exactly the entries they make stale.
+pub struct ResponseCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Mutex::default(),
+            capacity,
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // A capacity of 0 turns it off
+    pub fn from_config(config: &CacheConfig) -> Option<Self> {
+        (config.capacity > 0)
+            .then(|| Self::new(config.capacity, Duration::from_secs(config.ttl_seconds)))
+    }
+
+    pub fn get<T: Clone + 'static>(&self, key: &str) -> Option<T> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        let value = match inner.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.last_used = tick;
+                entry.value.downcast_ref::<T>().cloned()
+            }
+            Some(_) => {
+                inner.entries.remove(key);
+                None
+            }
+            None => None,
+        };
+
+        let counter = if value.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        value
+    }
+
+    pub fn insert<T: Send + Sync + 'static>(&self, key: String, value: T) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            let now = Instant::now();
+            inner.entries.retain(|_, x| x.expires_at > now);
+        }
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, x)| x.last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+
+        let entry = Entry {
+            value: Arc::new(value),
+            expires_at: Instant::now() + self.ttl,
+            last_used: tick,
+        };
+        inner.entries.insert(key, entry);
+    }
+
+    pub fn invalidate(&self, key: &str) {
+        self.inner.lock().unwrap().entries.remove(key);
+    }
+
+    pub fn invalidate_prefix(&self, prefix: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .retain(|k, _| !k.starts_with(prefix));
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            enabled: true,
+            entries: self.inner.lock().unwrap().entries.len() as i64,
+            capacity: self.capacity as i64,
+            hits: self.hits.load(Ordering::Relaxed) as i64,
+            misses: self.misses.load(Ordering::Relaxed) as i64,
+        }
+    }
+}
+
+pub const TAGS: &str = "tags";
+
+pub fn article_key(slug: &str) -> String {
+    format!("article:{slug}")
+}
+
+pub const LISTS: &str = "articles:";
+
+// Lists show favorites and comments counts, so any change to an article
+// drops all of them along with the article itself
+pub fn article_changed(state: &AppState, slug: &str) {
+    if let Some(cache) = &state.cache {
+        cache.invalidate(&article_key(slug));
+        cache.invalidate_prefix(LISTS);
+    }
+}
+
+pub fn tags_changed(state: &AppState) {
+    if let Some(cache) = &state.cache {
+        cache.invalidate(TAGS);
+    }
+}
+
+// For changes that show up everywhere, like an author's profile,
+// or whether their articles are visible at all
+pub fn everything_changed(state: &AppState) {
+    if let Some(cache) = &state.cache {
+        cache.clear();
+    }
+}
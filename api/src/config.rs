@@ -0,0 +1,462 @@
+use std::{
+    env, fmt, fs, io,
+    net::{Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use axum::http::{header::ETAG, HeaderName, HeaderValue};
+use chrono::Duration;
+use serde::Deserialize;
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer, ExposeHeaders};
+
+// Read when `REALWORLD_CONFIG` doesn't point somewhere else
+const DEFAULT_PATH: &str = "realworld.toml";
+
+// Settings come from the defaults below, then the TOML file, then the
+// environment, and are checked once at startup
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cors: CorsConfig,
+    pub tokens: TokenConfig,
+    pub log: LogConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub accounts: AccountConfig,
+    pub mail: MailConfig,
+    pub oidc: OidcConfig,
+    pub secrets: SecretConfig,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: SocketAddr,
+    /// Where the built frontend is served from
+    pub assets: PathBuf,
+    /// How long requests in flight and background tasks get to finish on shutdown
+    pub drain_seconds: u64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// `*` allows any origin
+    pub allowed_origins: Vec<String>,
+    /// `*` allows any header
+    pub allowed_headers: Vec<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenConfig {
+    pub session_days: i64,
+    pub impersonation_minutes: i64,
+    pub password_reset_minutes: i64,
+    pub email_verification_hours: i64,
+    /// How long a response is kept for retries with the same `Idempotency-Key`
+    pub idempotency_hours: i64,
+    /// A claimed key this old without a response belongs to a request that died
+    pub idempotency_abandoned_seconds: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// In the syntax of `RUST_LOG`
+    pub filter: String,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Falls back to the `DATABASE_URL` the Prisma schema points at
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// 0 turns the response cache off
+    pub capacity: usize,
+    pub ttl_seconds: u64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountConfig {
+    /// Whether users have to verify their email before writing articles or comments
+    pub require_verified_email: bool,
+    /// Open reports it takes to hide an article or comment until a moderator looks at it
+    pub report_threshold: i64,
+    /// How long an old username stays reserved for the user who gave it up
+    pub username_cooldown_days: i64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// Mails go out over SMTP when this is set, otherwise they're written to `outbox_dir`
+    pub smtp_url: Option<String>,
+    pub from: String,
+    pub outbox_dir: PathBuf,
+}
+
+/// Social login is only enabled when `issuer` is set
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    pub issuer: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretConfig {
+    pub hmac_key: String,
+    pub oidc_client_secret: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from((Ipv6Addr::UNSPECIFIED, 8080)),
+            assets: PathBuf::from("./frontend/dist"),
+            drain_seconds: 10,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".into()],
+            allowed_headers: [
+                "authorization",
+                "content-type",
+                "if-match",
+                "if-none-match",
+                "idempotency-key",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            session_days: 14,
+            impersonation_minutes: 60,
+            password_reset_minutes: 60,
+            email_verification_hours: 24,
+            idempotency_hours: 24,
+            idempotency_abandoned_seconds: 60,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            filter: "realworld-axum-prisma=debug,info,tower_http=debug".into(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            ttl_seconds: 30,
+        }
+    }
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            require_verified_email: false,
+            report_threshold: 5,
+            username_cooldown_days: 30,
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            smtp_url: None,
+            from: "noreply@realworld.dev".into(),
+            outbox_dir: PathBuf::from("./outbox"),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    // The setting or environment variable and what's wrong with it
+    Invalid(&'static str, &'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, e) => write!(f, "can't read {}: {e}", path.display()),
+            Self::Parse(e) => write!(f, "invalid configuration file: {e}"),
+            Self::Invalid(name, reason) => write!(f, "{name} {reason}"),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenvy::dotenv().ok();
+
+        let path = env::var("REALWORLD_CONFIG").ok().map(PathBuf::from);
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if PathBuf::from(DEFAULT_PATH).exists() => Self::from_file(DEFAULT_PATH.into())?,
+            None => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: PathBuf) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(&path).map_err(|e| ConfigError::Read(path, e))?;
+        Self::from_toml(&text)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        toml::from_str(text).map_err(ConfigError::Parse)
+    }
+
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(x) = var("BIND_ADDRESS")? {
+            self.server.address = x;
+        }
+        if let Some(x) = var("ASSETS_DIR")? {
+            self.server.assets = x;
+        }
+        if let Some(x) = var("DRAIN_SECONDS")? {
+            self.server.drain_seconds = x;
+        }
+        if let Some(x) = list("CORS_ALLOWED_ORIGINS") {
+            self.cors.allowed_origins = x;
+        }
+        if let Some(x) = list("CORS_ALLOWED_HEADERS") {
+            self.cors.allowed_headers = x;
+        }
+        if let Some(x) = var("SESSION_DAYS")? {
+            self.tokens.session_days = x;
+        }
+        if let Some(x) = var("IMPERSONATION_MINUTES")? {
+            self.tokens.impersonation_minutes = x;
+        }
+        if let Some(x) = var("PASSWORD_RESET_MINUTES")? {
+            self.tokens.password_reset_minutes = x;
+        }
+        if let Some(x) = var("EMAIL_VERIFICATION_HOURS")? {
+            self.tokens.email_verification_hours = x;
+        }
+        if let Some(x) = var("IDEMPOTENCY_HOURS")? {
+            self.tokens.idempotency_hours = x;
+        }
+        if let Some(x) = var("IDEMPOTENCY_ABANDONED_SECONDS")? {
+            self.tokens.idempotency_abandoned_seconds = x;
+        }
+        if let Some(x) = var("LOG_FORMAT")? {
+            self.log.format = x;
+        }
+        if let Some(x) = var("RUST_LOG")? {
+            self.log.filter = x;
+        }
+        if let Some(x) = var("DATABASE_URL")? {
+            self.database.url = Some(x);
+        }
+        if let Some(x) = var("CACHE_CAPACITY")? {
+            self.cache.capacity = x;
+        }
+        if let Some(x) = var("CACHE_TTL_SECONDS")? {
+            self.cache.ttl_seconds = x;
+        }
+        if let Some(x) = var("REQUIRE_VERIFIED_EMAIL")? {
+            self.accounts.require_verified_email = x;
+        }
+        if let Some(x) = var("REPORT_THRESHOLD")? {
+            self.accounts.report_threshold = x;
+        }
+        if let Some(x) = var("USERNAME_COOLDOWN_DAYS")? {
+            self.accounts.username_cooldown_days = x;
+        }
+        if let Some(x) = var("SMTP_URL")? {
+            self.mail.smtp_url = Some(x);
+        }
+        if let Some(x) = var("MAIL_FROM")? {
+            self.mail.from = x;
+        }
+        if let Some(x) = var("MAIL_OUTBOX_DIR")? {
+            self.mail.outbox_dir = x;
+        }
+        if let Some(x) = var("OIDC_ISSUER")? {
+            self.oidc.issuer = Some(x);
+        }
+        if let Some(x) = var("OIDC_CLIENT_ID")? {
+            self.oidc.client_id = Some(x);
+        }
+        if let Some(x) = var("OIDC_REDIRECT_URI")? {
+            self.oidc.redirect_uri = Some(x);
+        }
+        if let Some(x) = var("HMAC_KEY")? {
+            self.secrets.hmac_key = x;
+        }
+        if let Some(x) = var("OIDC_CLIENT_SECRET")? {
+            self.secrets.oidc_client_secret = Some(x);
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.secrets.hmac_key.is_empty() {
+            return Err(ConfigError::Invalid("secrets.hmac_key", "must be set"));
+        }
+
+        let tokens = &self.tokens;
+        let lifetimes = [
+            tokens.session_days,
+            tokens.impersonation_minutes,
+            tokens.password_reset_minutes,
+            tokens.email_verification_hours,
+            tokens.idempotency_hours,
+            tokens.idempotency_abandoned_seconds,
+        ];
+        if lifetimes.iter().any(|x| *x <= 0) {
+            return Err(ConfigError::Invalid("tokens", "lifetimes must be positive"));
+        }
+
+        if self.accounts.report_threshold < 1 {
+            return Err(ConfigError::Invalid("accounts.report_threshold", "must be at least 1"));
+        }
+        if self.accounts.username_cooldown_days < 0 {
+            return Err(ConfigError::Invalid("accounts.username_cooldown_days", "can't be negative"));
+        }
+
+        let oidc = &self.oidc;
+        if oidc.issuer.is_some() && (oidc.client_id.is_none() || oidc.redirect_uri.is_none()) {
+            return Err(ConfigError::Invalid("oidc", "needs a client_id and redirect_uri with the issuer"));
+        }
+
+        self.cors_layer().map(|_| ())
+    }
+
+    pub fn cors_layer(&self) -> Result<CorsLayer, ConfigError> {
+        let origins = &self.cors.allowed_origins;
+        let origins = if origins.iter().any(|x| x == "*") {
+            AllowOrigin::from(Any)
+        } else {
+            let origins = origins
+                .iter()
+                .map(|x| HeaderValue::from_str(x.trim_end_matches('/')))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ConfigError::Invalid("cors.allowed_origins", "must be origins or *"))?;
+            AllowOrigin::list(origins)
+        };
+
+        let headers = &self.cors.allowed_headers;
+        let headers = if headers.iter().any(|x| x == "*") {
+            AllowHeaders::from(Any)
+        } else {
+            let headers = headers
+                .iter()
+                .map(|x| HeaderName::from_str(x))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ConfigError::Invalid("cors.allowed_headers", "must be header names or *"))?;
+            AllowHeaders::list(headers)
+        };
+
+        // Browsers only let scripts read these if they're listed, and clients need
+        // the tag to send back as `If-Match`
+        let exposed = ExposeHeaders::list([ETAG, HeaderName::from_static("idempotent-replayed")]);
+
+        Ok(CorsLayer::new()
+            .allow_origin(origins)
+            .allow_headers(headers)
+            .allow_methods(Any)
+            .expose_headers(exposed))
+    }
+
+    pub fn session_length(&self) -> Duration {
+        Duration::days(self.tokens.session_days)
+    }
+
+    pub fn impersonation_length(&self) -> Duration {
+        Duration::minutes(self.tokens.impersonation_minutes)
+    }
+
+    pub fn password_reset_length(&self) -> Duration {
+        Duration::minutes(self.tokens.password_reset_minutes)
+    }
+
+    pub fn email_verification_length(&self) -> Duration {
+        Duration::hours(self.tokens.email_verification_hours)
+    }
+
+    pub fn idempotency_length(&self) -> Duration {
+        Duration::hours(self.tokens.idempotency_hours)
+    }
+
+    pub fn idempotency_abandoned_after(&self) -> Duration {
+        Duration::seconds(self.tokens.idempotency_abandoned_seconds)
+    }
+
+    pub fn username_cooldown(&self) -> Duration {
+        Duration::days(self.accounts.username_cooldown_days)
+    }
+}
+
+fn var<T: FromStr>(name: &'static str) -> Result<Option<T>, ConfigError> {
+    match env::var(name) {
+        Ok(x) => x
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError::Invalid(name, "can't be parsed")),
+        Err(_) => Ok(None),
+    }
+}
+
+fn list(name: &str) -> Option<Vec<String>> {
+    let value = env::var(name).ok()?;
+    Some(value.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect())
+}
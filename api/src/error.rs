@@ -1,4 +1,4 @@
-use std::{net::AddrParseError, env::VarError};
+use std::net::AddrParseError;
 
 use db::{NewClientError, QueryError, RecordNotFound, UniqueKeyViolation, DbErr};
 
@@ -9,12 +9,21 @@ use axum::{
 
 use tracing::info;
 
+use crate::config::ConfigError;
+
 // Error handling for the routes
 pub enum AppError {
     PrismaError(QueryError),
     NotFound,
+    BadRequest,
     Unathorized,
-    HashingError
+    Forbidden,
+    Conflict,
+    PreconditionFailed,
+    Unprocessable,
+    HashingError,
+    MailError,
+    OidcError,
 }
 
 impl From<QueryError> for AppError {
@@ -38,8 +47,15 @@ impl IntoResponse for AppError {
                 StatusCode::BAD_REQUEST
             },
             AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::BadRequest => StatusCode::BAD_REQUEST,
             AppError::Unathorized => StatusCode::UNAUTHORIZED,
-            AppError::HashingError => StatusCode::INTERNAL_SERVER_ERROR
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::Conflict => StatusCode::CONFLICT,
+            AppError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            AppError::Unprocessable => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::HashingError => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::MailError => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::OidcError => StatusCode::BAD_GATEWAY,
         };
 
         status.into_response()
@@ -51,8 +67,10 @@ impl IntoResponse for AppError {
 pub enum MainError {
     NewClientError(NewClientError),
     AddrParseError(AddrParseError),
-    HmacMissing(VarError),
+    Config(ConfigError),
+    MailerError,
     BindingError,
+    QueryError(QueryError),
 }
 
 impl From<DbErr> for AppError {
@@ -61,6 +79,8 @@ impl From<DbErr> for AppError {
             DbErr::NotFound => Self::NotFound,
             DbErr::QueryError(e) => Self::PrismaError(e),
             DbErr::Unauthorized => Self::Unathorized,
+            DbErr::Conflict => Self::Conflict,
+            DbErr::Stale => Self::Conflict,
         }
     }
 }
@@ -71,15 +91,21 @@ impl From<NewClientError> for MainError {
     }
 }
 
+impl From<QueryError> for MainError {
+    fn from(e: QueryError) -> Self {
+        Self::QueryError(e)
+    }
+}
+
 impl From<AddrParseError> for MainError {
     fn from(e: AddrParseError) -> Self {
         Self::AddrParseError(e)
     }
 }
 
-impl From<VarError> for MainError {
-    fn from(e: VarError) -> Self {
-        Self::HmacMissing(e)
+impl From<ConfigError> for MainError {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
     }
 }
 
@@ -0,0 +1,228 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use axum::{
+    http::{
+        header::{AUTHORIZATION, ETAG, IF_MATCH, IF_NONE_MATCH, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use db::DbErr;
+use serde::Serialize;
+use types::{
+    article::{Article, ArticleBody, MultipleArticles, Tags},
+    user::{Profile, ProfileBody, User, UserBody},
+};
+
+use crate::{error::AppError, AppResult};
+
+// What a response depends on: timestamps, counters and the viewer's flags.
+// Hashing these is cheaper than hashing the whole body, and the flags make
+// the tag differ between viewers just like the response does.
+pub trait Versioned {
+    fn version<H: Hasher>(&self, state: &mut H);
+
+    // Weak, since the same version can be serialized differently
+    fn etag(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.version(&mut hasher);
+        format!("W/\"{:016x}\"", hasher.finish())
+    }
+}
+
+// Articles and the current user are edited with `If-Match`, so their tag is strong
+// and leads with the version, ready to go straight back in that header. Counters
+// and flags change the response without an edit, so the hash follows the version
+// to keep `If-None-Match` honest.
+pub trait Editable: Versioned {
+    fn edit_version(&self) -> i32;
+
+    fn strong_etag(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.version(&mut hasher);
+        format!("\"{}-{:016x}\"", self.edit_version(), hasher.finish())
+    }
+}
+
+impl Versioned for ProfileBody {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        (&self.username, &self.bio, &self.image, self.following).hash(state);
+        if let Some(stats) = &self.stats {
+            (
+                stats.followers_count,
+                stats.following_count,
+                stats.articles_count,
+                stats.favorites_received,
+            )
+                .hash(state);
+        }
+    }
+}
+
+impl Versioned for Profile {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        self.profile.version(state);
+    }
+}
+
+impl Versioned for ArticleBody {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        (
+            &self.slug,
+            self.updated_at,
+            self.favorited,
+            self.favorites_count,
+            self.comments_count,
+        )
+            .hash(state);
+        // The author can change their profile without touching the article
+        self.author.version(state);
+    }
+}
+
+impl Versioned for Article {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        self.article.version(state);
+    }
+}
+
+impl Editable for Article {
+    fn edit_version(&self) -> i32 {
+        self.article.version
+    }
+}
+
+// The token is left out, it's fresh on every response
+impl Versioned for UserBody {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        (
+            &self.email,
+            &self.username,
+            &self.bio,
+            &self.image,
+            self.email_verified,
+            self.role,
+        )
+            .hash(state);
+    }
+}
+
+impl Versioned for User {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        self.user.version(state);
+    }
+}
+
+impl Editable for User {
+    fn edit_version(&self) -> i32 {
+        self.user.version
+    }
+}
+
+impl Versioned for MultipleArticles {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        self.articles_count.hash(state);
+        for article in &self.articles {
+            article.version(state);
+        }
+    }
+}
+
+impl Versioned for Tags {
+    fn version<H: Hasher>(&self, state: &mut H) {
+        self.tags.hash(state);
+    }
+}
+
+// Answers with 304 when the client already has this version. Either way the
+// response varies by who asks, so shared caches must not mix viewers up.
+pub fn conditional<T: Versioned + Serialize>(headers: &HeaderMap, value: T) -> Response {
+    let etag = value.etag();
+    respond(headers, etag, value)
+}
+
+// Same as `conditional`, with the strong tag
+pub fn conditional_editable<T: Editable + Serialize>(headers: &HeaderMap, value: T) -> Response {
+    let etag = value.strong_etag();
+    respond(headers, etag, value)
+}
+
+// The response to an edit, tagged so the client can send the next one
+pub fn tagged<T: Editable + Serialize>(value: T) -> Response {
+    let etag = value.strong_etag();
+    let mut response = Json(value).into_response();
+
+    // Just as personal as a conditional GET, so shared caches must keep it apart
+    let response_headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response_headers.insert(ETAG, etag);
+    }
+    response_headers.insert(VARY, HeaderValue::from_name(AUTHORIZATION));
+
+    response
+}
+
+fn respond<T: Serialize>(headers: &HeaderMap, etag: String, value: T) -> Response {
+    let mut response = if matches(headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        Json(value).into_response()
+    };
+
+    let response_headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response_headers.insert(ETAG, etag);
+    }
+    response_headers.insert(VARY, HeaderValue::from_name(AUTHORIZATION));
+
+    response
+}
+
+// Weak comparison, so `W/` is ignored on both sides
+fn matches(headers: &HeaderMap, etag: &str) -> bool {
+    let opaque = |x: &str| x.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .any(|x| x.trim() == "*" || opaque(x) == etag)
+}
+
+// Updates take the strong tag a client got from a GET or a PUT as `If-Match`,
+// and only its version is compared, so a new favorite doesn't fail an edit. A `*`
+// or no header at all leaves it to the version in the body, if there is one.
+pub fn if_match_version(headers: &HeaderMap) -> AppResult<Option<i32>> {
+    let value = match headers.get(IF_MATCH) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let value = value.to_str().map_err(|_| AppError::BadRequest)?.trim();
+    if value == "*" {
+        return Ok(None);
+    }
+
+    // Weak tags and anything else we didn't hand out can never match
+    value
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .and_then(|x| x.split_once('-'))
+        .and_then(|(version, _)| version.parse().ok())
+        .map(Some)
+        .ok_or(AppError::PreconditionFailed)
+}
+
+// A stale `If-Match` is a failed precondition, a stale body version a conflict
+pub fn stale_as_precondition(if_match: Option<i32>) -> impl FnOnce(DbErr) -> AppError {
+    move |e| match e {
+        DbErr::Stale if if_match.is_some() => AppError::PreconditionFailed,
+        e => e.into(),
+    }
+}
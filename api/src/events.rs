@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use db::query::notification_with_actor;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use types::{article::ArticleBody, comment::CommentBody, notification::NotificationBody};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    /// A comment was posted on the article with this slug
+    Comment {
+        slug: String,
+        author_id: String,
+        comment: CommentBody,
+    },
+    Notification {
+        recipient_id: String,
+        notification: NotificationBody,
+    },
+    /// A new article, for the feed of everyone following the author
+    Article {
+        author_id: String,
+        article: ArticleBody,
+    },
+}
+
+impl Event {
+    pub fn notification(data: notification_with_actor::Data) -> Self {
+        Event::Notification {
+            recipient_id: data.recipient_id.clone(),
+            notification: data.into_notification_body(),
+        }
+    }
+}
+
+// Carries events from the handlers that produce them to every open stream.
+// The local backend only reaches streams on this instance, running several
+// instances takes a backend that relays events through a shared broker.
+#[async_trait]
+pub trait EventBackend: Send + Sync {
+    async fn publish(&self, event: Event);
+    fn subscribe(&self) -> broadcast::Receiver<Event>;
+}
+
+pub struct LocalBackend {
+    sender: broadcast::Sender<Event>,
+}
+
+impl LocalBackend {
+    /// Streams that fall more than `capacity` events behind skip the ones they missed
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+}
+
+impl Default for LocalBackend {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+#[async_trait]
+impl EventBackend for LocalBackend {
+    async fn publish(&self, event: Event) {
+        // Fails only when nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+// What a single stream gets to see, fixed when it connects.
+pub struct Subscriber {
+    pub user_id: String,
+    /// Slugs of the articles the client is looking at
+    pub articles: HashSet<String>,
+    pub follows: HashSet<String>,
+    /// Users the subscriber blocked or muted
+    pub ignored: HashSet<String>,
+}
+
+impl Subscriber {
+    pub fn filter(&self, event: Event) -> Option<Event> {
+        match event {
+            Event::Comment { slug, author_id, mut comment } => {
+                if !self.articles.contains(&slug) || self.ignored.contains(&author_id) {
+                    return None;
+                }
+                comment.author.profile.following = self.follows.contains(&author_id);
+                Some(Event::Comment { slug, author_id, comment })
+            }
+            Event::Notification { recipient_id, notification } => {
+                (recipient_id == self.user_id)
+                    .then_some(Event::Notification { recipient_id, notification })
+            }
+            Event::Article { author_id, article } => {
+                (self.follows.contains(&author_id) && !self.ignored.contains(&author_id))
+                    .then_some(Event::Article { author_id, article })
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{error::AppError, AppState};
+use crate::{error::AppError, hashing::hash_token, AppResult, AppState};
 use async_trait::async_trait;
 use axum::{
     extract::{FromRequestParts, State, FromRef},
@@ -8,13 +8,25 @@ use hmac::{Hmac, Mac};
 use jwt::{SignWithKey, VerifyWithKey};
 use tracing::error;
 use sha2::Sha384;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
+use db::{
+    mutation::Mutation,
+    prisma::user::Data as UserData,
+    query::{is_suspended, Query},
+};
+use types::{token::Scope, user::Role};
 
 const SCHEME_PREFIX: &str = "Token ";
+pub const ACCESS_TOKEN_PREFIX: &str = "rwpat_";
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct AuthUser {
     pub user_id: String,
+    pub role: Role,
+    /// `None` for regular sessions, the granted scopes for personal access tokens.
+    pub scopes: Option<Vec<Scope>>,
+    /// The admin behind a read-only impersonation session
+    pub impersonator_id: Option<String>,
 }
 
 #[derive(Debug)]
@@ -23,25 +35,109 @@ pub struct MaybeAuthUser(pub Option<AuthUser>);
 #[derive(serde::Serialize, serde::Deserialize)]
 struct AuthUserClaims {
     user_id: String,
+    /// Missing from tokens issued before roles existed.
+    #[serde(default)]
+    role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    impersonator_id: Option<String>,
+    /// Standard JWT `iat` claim, missing from older tokens.
+    #[serde(default)]
+    iat: i64,
     /// Standard JWT `exp` claim.
     exp: i64,
 }
 
+/// Proves that a user passed the password step of a login with 2FA enabled.
+/// Its claims don't deserialize as `AuthUserClaims`, so it can't be used as a session token.
+pub struct TwoFactorChallenge {
+    pub user_id: String,
+    /// The `LoginChallenge` row that counts the codes tried with this token
+    pub challenge_id: String,
+}
+
+/// Like the challenge, a stream ticket can't be used as a session token
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StreamTicketClaims {
+    stream_user_id: String,
+    scopes: Option<Vec<Scope>>,
+    impersonator_id: Option<String>,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TwoFactorChallengeClaims {
+    challenge_user_id: String,
+    challenge_id: String,
+    exp: i64,
+}
+
 impl AuthUser {
+    pub fn for_user(user: &UserData) -> Self {
+        Self {
+            user_id: user.id.clone(),
+            role: user.role.into(),
+            scopes: None,
+            impersonator_id: None,
+        }
+    }
+
+    pub fn require_scope(&self, scope: Scope) -> AppResult<()> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) => {
+                error!("Access token is missing the {scope} scope");
+                Err(AppError::Forbidden)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Account management, and anything else done in the user's own name, can't
+    // be done with a personal access token or by an admin impersonating the user
+    pub fn require_session(&self) -> AppResult<()> {
+        match (&self.scopes, &self.impersonator_id) {
+            (None, None) => Ok(()),
+            _ => Err(AppError::Forbidden),
+        }
+    }
+
     pub fn to_jwt(&self, ctx: &AppState) -> String {
-        let default_session_length = Duration::weeks(2);
-        let hmac = Hmac::<Sha384>::new_from_slice(ctx.hmac_key.as_bytes())
+        let now = Utc::now();
+        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.secrets.hmac_key.as_bytes())
             .expect("HMAC-SHA-384 can accept any key length");
 
         AuthUserClaims {
             user_id: self.user_id.clone(),
-            exp: (Utc::now() + default_session_length).timestamp(),
+            role: self.role,
+            impersonator_id: None,
+            iat: now.timestamp(),
+            exp: (now + ctx.config.session_length()).timestamp(),
         }
         .sign_with_key(&hmac)
         .expect("HMAC signing should be infallible")
     }
 
-    fn from_authorization(ctx: &AppState, auth_header: &HeaderValue) -> Result<Self, AppError> {
+    /// A short session that lets an admin see the app as `user_id` does, without being able to change anything
+    pub fn impersonation_jwt(ctx: &AppState, user_id: String, impersonator_id: String) -> (String, DateTime<Utc>) {
+        let now = Utc::now();
+        let expires_at = now + ctx.config.impersonation_length();
+        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.secrets.hmac_key.as_bytes())
+            .expect("HMAC-SHA-384 can accept any key length");
+
+        let token = AuthUserClaims {
+            user_id,
+            role: Role::User,
+            impersonator_id: Some(impersonator_id),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+        }
+        .sign_with_key(&hmac)
+        .expect("HMAC signing should be infallible");
+
+        (token, expires_at)
+    }
+
+    async fn from_authorization(ctx: &AppState, auth_header: &HeaderValue) -> Result<Self, AppError> {
         let auth_header = auth_header.to_str().map_err(|e| {
             error!("Couldn't encode auth header as string, {e}");
             AppError::Unathorized
@@ -54,10 +150,14 @@ impl AuthUser {
 
         let token = &auth_header[SCHEME_PREFIX.len()..];
 
+        if token.starts_with(ACCESS_TOKEN_PREFIX) {
+            return Self::from_access_token(ctx, token).await;
+        }
+
         let jwt = jwt::Token::<jwt::Header, AuthUserClaims, _>::parse_unverified(token)
             .map_err(|_e| AppError::Unathorized)?;
 
-        let hmac = Hmac::<Sha384>::new_from_slice(ctx.hmac_key.as_bytes())
+        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.secrets.hmac_key.as_bytes())
             .expect("HMAC-SHA-384 can accept any key length");
 
         let jwt = jwt
@@ -74,8 +174,165 @@ impl AuthUser {
             return Err(AppError::Unathorized);
         }
 
+        let role = Self::current_role(ctx, &claims.user_id, claims.iat).await?;
+
+        // Impersonation sessions can only read
+        let scopes = claims.impersonator_id.as_ref().map(|_| vec![Scope::Read]);
+
         Ok(Self {
             user_id: claims.user_id,
+            role,
+            scopes,
+            impersonator_id: claims.impersonator_id,
+        })
+    }
+
+    // Tokens outlive suspensions and revocations, so the user is checked every time
+    async fn current_role(ctx: &AppState, user_id: &str, issued_at: i64) -> Result<Role, AppError> {
+        let status = Query::get_user_status(&ctx.client, user_id.to_string())
+            .await
+            .map_err(|_e| {
+                error!("Token for an unknown user");
+                AppError::Unathorized
+            })?;
+
+        if is_suspended(status.suspended_at, status.suspended_until) {
+            error!("User {} is suspended", user_id);
+            return Err(AppError::Forbidden);
+        }
+
+        if matches!(status.sessions_revoked_at, Some(revoked_at) if issued_at < revoked_at.timestamp()) {
+            error!("Revoked token");
+            return Err(AppError::Unathorized);
+        }
+
+        Ok(status.role.into())
+    }
+
+    /// A short lived token that only opens the event stream, for clients that
+    /// can't send an `Authorization` header and have to put it in the url
+    pub fn stream_ticket(&self, ctx: &AppState) -> (String, DateTime<Utc>) {
+        let now = Utc::now();
+        let expires_at = now + Duration::minutes(1);
+        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.secrets.hmac_key.as_bytes())
+            .expect("HMAC-SHA-384 can accept any key length");
+
+        let token = StreamTicketClaims {
+            stream_user_id: self.user_id.clone(),
+            scopes: self.scopes.clone(),
+            impersonator_id: self.impersonator_id.clone(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+        }
+        .sign_with_key(&hmac)
+        .expect("HMAC signing should be infallible");
+
+        (token, expires_at)
+    }
+
+    pub async fn from_stream_ticket(ctx: &AppState, token: &str) -> Result<Self, AppError> {
+        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.secrets.hmac_key.as_bytes())
+            .expect("HMAC-SHA-384 can accept any key length");
+
+        let claims: StreamTicketClaims = token.verify_with_key(&hmac).map_err(|e| {
+            error!("Error on verifying stream ticket, {e}");
+            AppError::Unathorized
+        })?;
+
+        if claims.exp < Utc::now().timestamp() {
+            error!("Outdated stream ticket");
+            return Err(AppError::Unathorized);
+        }
+
+        let role = Self::current_role(ctx, &claims.stream_user_id, claims.iat).await?;
+
+        Ok(Self {
+            user_id: claims.stream_user_id,
+            role,
+            scopes: claims.scopes,
+            impersonator_id: claims.impersonator_id,
+        })
+    }
+
+    async fn from_access_token(ctx: &AppState, token: &str) -> Result<Self, AppError> {
+        let access_token = Query::get_access_token_by_hash(&ctx.client, hash_token(token))
+            .await
+            .map_err(|_e| {
+                error!("Unknown access token");
+                AppError::Unathorized
+            })?;
+
+        let now = Utc::now();
+
+        if matches!(access_token.expires_at, Some(expires_at) if expires_at < now) {
+            error!("Outdated access token");
+            return Err(AppError::Unathorized);
+        }
+
+        if is_suspended(access_token.user.suspended_at, access_token.user.suspended_until) {
+            error!("User {} is suspended", access_token.user_id);
+            return Err(AppError::Forbidden);
+        }
+
+        // No need to write on every single request
+        let recently_used = matches!(
+            access_token.last_used_at,
+            Some(last_used) if now.signed_duration_since(last_used) < Duration::minutes(1)
+        );
+        if !recently_used {
+            Mutation::touch_access_token(&ctx.client, access_token.id).await?;
+        }
+
+        Ok(Self {
+            user_id: access_token.user_id,
+            role: access_token.user.role.into(),
+            scopes: Some(
+                access_token
+                    .scopes
+                    .iter()
+                    .filter_map(|x| x.parse().ok())
+                    .collect(),
+            ),
+            impersonator_id: None,
+        })
+    }
+}
+
+impl TwoFactorChallenge {
+    pub fn length() -> Duration {
+        Duration::minutes(5)
+    }
+
+    pub fn to_jwt(&self, ctx: &AppState) -> String {
+        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.secrets.hmac_key.as_bytes())
+            .expect("HMAC-SHA-384 can accept any key length");
+
+        TwoFactorChallengeClaims {
+            challenge_user_id: self.user_id.clone(),
+            challenge_id: self.challenge_id.clone(),
+            exp: (Utc::now() + Self::length()).timestamp(),
+        }
+        .sign_with_key(&hmac)
+        .expect("HMAC signing should be infallible")
+    }
+
+    pub fn from_jwt(ctx: &AppState, token: &str) -> Result<Self, AppError> {
+        let hmac = Hmac::<Sha384>::new_from_slice(ctx.config.secrets.hmac_key.as_bytes())
+            .expect("HMAC-SHA-384 can accept any key length");
+
+        let claims: TwoFactorChallengeClaims = token.verify_with_key(&hmac).map_err(|e| {
+            error!("Error on verifying challenge token, {e}");
+            AppError::Unathorized
+        })?;
+
+        if claims.exp < Utc::now().timestamp() {
+            error!("Outdated challenge token");
+            return Err(AppError::Unathorized);
+        }
+
+        Ok(Self {
+            user_id: claims.challenge_user_id,
+            challenge_id: claims.challenge_id,
         })
     }
 }
@@ -103,7 +360,7 @@ where
                 AppError::Unathorized 
             })?;
 
-        Self::from_authorization(&state, auth_header)
+        Self::from_authorization(&state, auth_header).await
     }
 }
 
@@ -129,7 +386,11 @@ where
 
         match auth_header {
             Ok(header) => {
-                let auth = AuthUser::from_authorization(&state, header).ok();
+                // Tokens that can't read are treated like anonymous requests
+                let auth = AuthUser::from_authorization(&state, header)
+                    .await
+                    .ok()
+                    .filter(|x| x.require_scope(Scope::Read).is_ok());
                 Ok(Self(auth))
             },
             Err(e) => {
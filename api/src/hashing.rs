@@ -1,5 +1,7 @@
 use argon2::{PasswordHash, Argon2};
 use argon2::password_hash::SaltString;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 
 use crate::AppResult;
 use crate::error::AppError;
@@ -33,4 +35,24 @@ pub async fn verify_password(password: String, password_hash: String) -> AppResu
     })
     .await
     .unwrap()
-}
\ No newline at end of file
+}
+
+// Reset and verification tokens are random and long enough that a fast hash is fine,
+// and it lets us look them up by their hash directly.
+pub fn generate_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    let token = to_hex(&bytes);
+    let hash = hash_token(&token);
+
+    (token, hash)
+}
+
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
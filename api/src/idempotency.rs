@@ -0,0 +1,121 @@
+use std::future::Future;
+
+use axum::{
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use db::mutation::{IdempotencyClaim, Mutation};
+use serde::Serialize;
+use tracing::error;
+
+use crate::{error::AppError, hashing::hash_token, AppJsonResult, AppResult, AppState};
+
+const HEADER: &str = "idempotency-key";
+
+pub struct IdempotencyKey {
+    user_id: String,
+    key: String,
+    request_hash: String,
+}
+
+impl IdempotencyKey {
+    // Reads the `Idempotency-Key` header, requests without one just run
+    pub fn from_request<T: Serialize>(
+        headers: &HeaderMap,
+        user_id: &str,
+        path: &str,
+        payload: &T,
+    ) -> AppResult<Option<Self>> {
+        let key = match headers.get(HEADER) {
+            Some(x) => x.to_str().map_err(|_| AppError::BadRequest)?.trim(),
+            None => return Ok(None),
+        };
+        if key.is_empty() || key.len() > 255 {
+            return Err(AppError::BadRequest);
+        }
+
+        let payload = serde_json::to_string(payload).map_err(|_| AppError::BadRequest)?;
+
+        Ok(Some(Self {
+            user_id: user_id.to_string(),
+            key: key.to_string(),
+            request_hash: hash_token(&format!("{path}\n{payload}")),
+        }))
+    }
+}
+
+// Runs the request once per key. Retries get the stored response back, marked
+// with `Idempotent-Replayed`, while the first one is still running they get a 409.
+pub async fn idempotent<T, F>(state: &AppState, key: Option<IdempotencyKey>, run: F) -> AppResult<Response>
+where
+    T: Serialize + Send + 'static,
+    F: Future<Output = AppJsonResult<T>> + Send + 'static,
+{
+    let key = match key {
+        Some(x) => x,
+        None => return run.await.map(IntoResponse::into_response),
+    };
+
+    let claim = Mutation::claim_idempotency_key(
+        &state.client,
+        key.user_id.clone(),
+        key.key.clone(),
+        key.request_hash,
+        state.config.idempotency_length(),
+        state.config.idempotency_abandoned_after(),
+    )
+    .await?;
+
+    match claim {
+        IdempotencyClaim::Claimed => {}
+        IdempotencyClaim::Completed { status, body } => return Ok(replay(status, body)),
+        IdempotencyClaim::InFlight => return Err(AppError::Conflict),
+        IdempotencyClaim::Mismatch => return Err(AppError::Unprocessable),
+    }
+
+    // The request and its stored response run in their own task, so a client
+    // hanging up can't stop the write halfway or leave it without a response
+    // for the retry to get back
+    let client = state.client.clone();
+    let task = state.shutdown.spawn(async move {
+        let result = run.await;
+        let body = result
+            .as_ref()
+            .ok()
+            .and_then(|Json(x)| serde_json::to_string(x).ok());
+
+        // The request itself went through either way, so its result stands. A key
+        // left claimed is given up on once it's abandoned.
+        let (user_id, id) = (key.user_id.clone(), key.key.clone());
+        let stored = match body {
+            Some(body) => Mutation::complete_idempotency_key(&client, user_id, id, 200, body).await,
+            None => Mutation::release_idempotency_key(&client, user_id, id).await,
+        };
+        if stored.is_err() {
+            error!("Couldn't store the response for idempotency key {} of user {}", key.key, key.user_id);
+        }
+
+        result
+    });
+
+    let result = task
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+
+    result.map(IntoResponse::into_response)
+}
+
+fn replay(status: i32, body: String) -> Response {
+    let status = u16::try_from(status)
+        .ok()
+        .and_then(|x| StatusCode::from_u16(x).ok())
+        .unwrap_or(StatusCode::OK);
+
+    let mut response = (status, [(CONTENT_TYPE, "application/json")], body).into_response();
+    response
+        .headers_mut()
+        .insert("idempotent-replayed", HeaderValue::from_static("true"));
+
+    response
+}
@@ -1,72 +1,157 @@
+mod authorization;
+pub mod cache;
+pub mod config;
 pub mod error;
+pub mod etag;
+pub mod events;
 mod extractor;
 mod hashing;
+mod idempotency;
+pub mod mail;
+pub mod oidc;
+pub mod totp;
 mod util;
 
+pub mod shutdown;
+
 mod routes;
 
 pub type AppResult<T> = Result<T, AppError>;
 type AppJsonResult<T> = AppResult<Json<T>>;
 
-use std::{env, net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 
 use axum::{Json, Router, Server};
 use axum_extra::routing::SpaRouter;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::TraceLayer,
-};
-use tracing::info;
+use tower_http::trace::TraceLayer;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use db::{get_client, prisma::PrismaClient};
+use db::{get_client, mutation::Mutation, prisma::PrismaClient};
+use cache::ResponseCache;
+use config::{Config, LogFormat};
 use error::{AppError, MainError};
-use routes::{article, comment, profile, user};
+use events::{EventBackend, LocalBackend};
+use mail::{mailer_from_config, Mailer};
+use oidc::OidcProvider;
+use shutdown::Shutdown;
+use routes::{
+    admin, article, comment, health, identity, notification, profile, report, stream, token,
+    two_factor, user,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub client: Arc<PrismaClient>,
-    pub hmac_key: Arc<String>,
+    pub mailer: Arc<dyn Mailer>,
+    pub oidc: Option<Arc<OidcProvider>>,
+    pub events: Arc<dyn EventBackend>,
+    /// Responses to anonymous reads, `None` when caching is turned off
+    pub cache: Option<Arc<ResponseCache>>,
+    pub config: Arc<Config>,
+    pub shutdown: Shutdown,
 }
 
 pub async fn run() -> Result<(), MainError> {
-    //dotenvy::dotenv().ok();
-
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG")
-                .unwrap_or_else(|_| "realworld-axum-prisma=debug,info,tower_http=debug".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let config = Config::load()?;
+    init_tracing(&config);
 
-    let client = Arc::new(get_client().await?);
-    let hmac_key = Arc::new(env::var("HMAC_KEY")?);
+    let client = Arc::new(get_client(config.database.url.clone()).await?);
+    let mailer = mailer_from_config(&config.mail)?;
 
-    let state = AppState { client, hmac_key };
+    let state = AppState {
+        client,
+        mailer,
+        oidc: OidcProvider::from_config(&config).map(Arc::new),
+        events: Arc::new(LocalBackend::default()),
+        cache: ResponseCache::from_config(&config.cache).map(Arc::new),
+        config: Arc::new(config),
+        shutdown: Shutdown::default(),
+    };
 
-    let spa = SpaRouter::new("/assets", "./frontend/dist").index_file("index.html");
+    let config = state.config.clone();
+    let shutdown = state.shutdown.clone();
+    let spa = SpaRouter::new("/assets", &config.server.assets).index_file("index.html");
     let app = app(state)
         .merge(spa);
 
-    let addr: SocketAddr = "[::]:8080".parse()?;
+    let addr = config.server.address;
 
     info!("Server listening on {}", &addr);
-    Server::bind(&addr)
+    let server = Server::try_bind(&addr)
+        .map_err(|_| MainError::BindingError)?
         .serve(app.into_make_service())
-        .await
-        .map_err(|_| MainError::BindingError)?;
+        .with_graceful_shutdown(shutdown.clone().wait());
+    tokio::pin!(server);
 
+    // Once a signal comes, new connections are refused and event streams end,
+    // while requests already in flight get until the drain timeout to finish
+    tokio::select! {
+        result = &mut server => result.map_err(|_| MainError::BindingError)?,
+        _ = shutdown::signal() => {
+            info!("Shutting down, draining connections");
+            shutdown.trigger();
+
+            // Background work, like mails already promised, shares the same timeout
+            let drain = std::time::Duration::from_secs(config.server.drain_seconds);
+            let finish = async {
+                let result = server.await;
+                shutdown.drain().await;
+                result
+            };
+            match tokio::time::timeout(drain, finish).await {
+                Ok(result) => result.map_err(|_| MainError::BindingError)?,
+                Err(_) => warn!("Requests or tasks still running after {}s, stopping them", drain.as_secs()),
+            }
+        }
+    }
+
+    info!("Server stopped");
     Ok(())
 }
 
+// Run as `realworld reconcile-counters`, e.g. from a cron job
+pub async fn reconcile_counters() -> Result<(), MainError> {
+    let config = Config::load()?;
+    init_tracing(&config);
+
+    let client = get_client(config.database.url.clone()).await?;
+    let fixed = Mutation::reconcile_counters(&client).await?;
+
+    info!("Reconciled the counters of {} articles", fixed);
+    Ok(())
+}
+
+fn init_tracing(config: &Config) {
+    let registry = tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new(&config.log.filter));
+
+    match config.log.format {
+        LogFormat::Pretty => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Json => registry.with(tracing_subscriber::fmt::layer().json()).init(),
+    }
+}
+
 pub fn app(state: AppState) -> Router {
+    let cors = state
+        .config
+        .cors_layer()
+        .expect("CORS settings are checked when the config is loaded");
+
     Router::new()
+        .merge(health::create_routes())
         .merge(article::create_routes())
         .merge(comment::create_routes())
         .merge(profile::create_routes())
         .merge(user::create_routes())
+        .merge(two_factor::create_routes())
+        .merge(token::create_routes())
+        .merge(identity::create_routes())
+        .merge(admin::create_routes())
+        .merge(report::create_routes())
+        .merge(notification::create_routes())
+        .merge(stream::create_routes())
         .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any))
+        .layer(cors)
         .with_state(state)
 }
@@ -0,0 +1,141 @@
+use std::{path::PathBuf, sync::{Arc, Mutex}};
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use lettre::{
+    message::Mailbox, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use tracing::{error, info};
+
+use crate::{
+    config::MailConfig,
+    error::{AppError, MainError},
+    AppResult,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// How long a token in a mail stays valid, e.g. "an hour" or "90 minutes"
+pub fn lifetime(length: Duration) -> String {
+    let (count, unit) = if length.num_days() > 0 && length == Duration::days(length.num_days()) {
+        (length.num_days(), "day")
+    } else if length.num_hours() > 0 && length == Duration::hours(length.num_hours()) {
+        (length.num_hours(), "hour")
+    } else {
+        (length.num_minutes(), "minute")
+    };
+
+    match count {
+        1 if unit == "hour" => "an hour".into(),
+        1 => format!("a {unit}"),
+        _ => format!("{count} {unit}s"),
+    }
+}
+
+// Everything that sends mail goes through this, so the transport can be
+// swapped for local development and tests.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> AppResult<()>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(url: &str, from: &str) -> Result<Self, MainError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+            .map_err(|_| MainError::MailerError)?
+            .build();
+        let from = from.parse().map_err(|_| MainError::MailerError)?;
+
+        Ok(Self { transport, from })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> AppResult<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse().map_err(|_| AppError::MailError)?)
+            .subject(mail.subject)
+            .body(mail.body)
+            .map_err(|_| AppError::MailError)?;
+
+        self.transport.send(message).await.map_err(|e| {
+            error!("Couldn't send mail, {e}");
+            AppError::MailError
+        })?;
+
+        Ok(())
+    }
+}
+
+// Writes every mail as a file in a directory, for local development.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> AppResult<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|_| AppError::MailError)?;
+
+        let path = self
+            .dir
+            .join(format!("{}-{}.eml", Utc::now().timestamp_millis(), mail.to));
+        let contents = format!("To: {}\nSubject: {}\n\n{}\n", mail.to, mail.subject, mail.body);
+
+        tokio::fs::write(&path, contents).await.map_err(|e| {
+            error!("Couldn't write mail to {}, {e}", path.display());
+            AppError::MailError
+        })?;
+
+        info!("Mail to {} written to {}", mail.to, path.display());
+        Ok(())
+    }
+}
+
+// Keeps every mail in memory so tests can inspect what would have been sent.
+#[derive(Default)]
+pub struct MemoryMailer {
+    outbox: Mutex<Vec<Mail>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Mail> {
+        self.outbox.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, mail: Mail) -> AppResult<()> {
+        self.outbox.lock().unwrap().push(mail);
+        Ok(())
+    }
+}
+
+// Uses SMTP when `smtp_url` is set, otherwise drops mails in `outbox_dir`.
+pub fn mailer_from_config(config: &MailConfig) -> Result<Arc<dyn Mailer>, MainError> {
+    match &config.smtp_url {
+        Some(url) => Ok(Arc::new(SmtpMailer::new(url, &config.from)?)),
+        None => Ok(Arc::new(FileMailer::new(config.outbox_dir.clone()))),
+    }
+}
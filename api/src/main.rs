@@ -1,7 +1,10 @@
-use realworld::{run, error::MainError};
+use realworld::{run, reconcile_counters, error::MainError};
 
 
 #[tokio::main]
 async fn main() -> Result<(), MainError> {
-    run().await
+    match std::env::args().nth(1).as_deref() {
+        Some("reconcile-counters") => reconcile_counters().await,
+        _ => run().await,
+    }
 }
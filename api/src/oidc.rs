@@ -0,0 +1,159 @@
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tracing::error;
+
+use crate::{config::Config, error::AppError, AppResult};
+
+// Client for a single OpenID Connect provider, using the authorization code flow with PKCE.
+// The user info comes from the provider's userinfo endpoint over TLS, so the ID token
+// doesn't need to be verified (OIDC Core 3.1.3.7).
+pub struct OidcProvider {
+    pub issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    http: reqwest::Client,
+    discovery: OnceCell<Discovery>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+impl OidcProvider {
+    pub fn new(
+        issuer: String,
+        client_id: String,
+        client_secret: Option<String>,
+        redirect_uri: String,
+    ) -> Self {
+        Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret,
+            redirect_uri,
+            http: reqwest::Client::new(),
+            discovery: OnceCell::new(),
+        }
+    }
+
+    // Social login is only enabled when an issuer is configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let oidc = &config.oidc;
+
+        Some(Self::new(
+            oidc.issuer.clone()?,
+            oidc.client_id.clone()?,
+            config.secrets.oidc_client_secret.clone(),
+            oidc.redirect_uri.clone()?,
+        ))
+    }
+
+    async fn discovery(&self) -> AppResult<&Discovery> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer);
+                self.http
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|x| x.error_for_status())
+                    .map_err(provider_error)?
+                    .json::<Discovery>()
+                    .await
+                    .map_err(provider_error)
+            })
+            .await
+    }
+
+    pub async fn authorization_url(&self, state: &str, code_verifier: &str) -> AppResult<String> {
+        let discovery = self.discovery().await?;
+        let code_challenge = pkce_challenge(code_verifier);
+
+        let url = Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", "openid email profile"),
+                ("state", state),
+                ("code_challenge", &code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| {
+            error!("Invalid authorization endpoint, {e}");
+            AppError::OidcError
+        })?;
+
+        Ok(url.into())
+    }
+
+    pub async fn exchange(&self, code: &str, code_verifier: &str) -> AppResult<UserInfo> {
+        let discovery = self.discovery().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+
+        let token: TokenResponse = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+
+        self.http
+            .get(&discovery.userinfo_endpoint)
+            .bearer_auth(token.access_token)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)
+    }
+}
+
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn provider_error(e: reqwest::Error) -> AppError {
+    error!("OIDC provider request failed, {e}");
+    AppError::OidcError
+}
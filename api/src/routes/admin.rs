@@ -0,0 +1,263 @@
+use axum::{
+    extract::{Json, Path, Query as UrlQuery, State},
+    http::StatusCode,
+    routing::{get, post, put},
+    Router,
+};
+use chrono::Utc;
+use tracing::error;
+
+use db::{mutation::Mutation, prisma::Role as DbRole, query::Query};
+
+use crate::{
+    authorization::require_admin, cache, error::AppError, extractor::AuthUser, hashing::generate_token,
+    mail::{lifetime, Mail}, AppJsonResult, AppState,
+};
+
+use types::{
+    admin::{
+        AdminUser, AdminUsers, AuditLog, Cache, Impersonation, ImpersonationBody, PageParams,
+        Suspend, UserSearchParams,
+    },
+    user::{UpdateRole, UpdateRoleBody},
+};
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/admin/users", get(handle_list_users))
+        .route("/api/admin/users/:username", get(handle_get_user))
+        .route("/api/admin/users/:username/role", put(handle_update_role))
+        .route(
+            "/api/admin/users/:username/suspension",
+            put(handle_suspend_user).delete(handle_lift_suspension),
+        )
+        .route(
+            "/api/admin/users/:username/password-reset",
+            post(handle_force_password_reset),
+        )
+        .route(
+            "/api/admin/users/:username/impersonate",
+            post(handle_impersonate_user),
+        )
+        .route("/api/admin/audit", get(handle_get_audit_log))
+        .route("/api/admin/cache", get(handle_get_cache_stats))
+}
+
+async fn handle_list_users(
+    auth_user: AuthUser,
+    UrlQuery(params): UrlQuery<UserSearchParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<AdminUsers> {
+    require_admin(&auth_user)?;
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let q = params.q.filter(|x| !x.trim().is_empty());
+
+    let (users, users_count) = Query::search_users(&state.client, q, limit, offset).await?;
+
+    Ok(Json(AdminUsers {
+        users: users.into_iter().map(|x| x.into_admin_user()).collect(),
+        users_count,
+    }))
+}
+
+async fn handle_get_user(
+    auth_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> AppJsonResult<AdminUser> {
+    require_admin(&auth_user)?;
+
+    let user = Query::get_user_by_current_username(&state.client, username).await?;
+
+    Ok(Json(AdminUser {
+        user: user.into_admin_user(),
+    }))
+}
+
+async fn handle_update_role(
+    auth_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateRole>,
+) -> AppJsonResult<UpdateRole> {
+    require_admin(&auth_user)?;
+
+    let user = Mutation::set_user_role(
+        &state.client,
+        auth_user.user_id,
+        username,
+        input.user.role.into(),
+    )
+    .await?;
+
+    Ok(Json(UpdateRole {
+        user: UpdateRoleBody {
+            role: user.role.into(),
+        },
+    }))
+}
+
+async fn handle_suspend_user(
+    auth_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<Suspend>,
+) -> AppJsonResult<AdminUser> {
+    require_admin(&auth_user)?;
+
+    if input.suspension.reason.trim().is_empty()
+        || matches!(input.suspension.expires_at, Some(x) if x < Utc::now())
+    {
+        return Err(AppError::BadRequest);
+    }
+
+    let user = Query::get_user_by_current_username(&state.client, username).await?;
+
+    // Admins have to be demoted first, which also keeps anyone from locking themselves out
+    if matches!(user.role, DbRole::Admin) {
+        return Err(AppError::Forbidden);
+    }
+
+    let user = Mutation::suspend_user(
+        &state.client,
+        auth_user.user_id,
+        user.id,
+        input.suspension.reason,
+        input.suspension.expires_at,
+    )
+    .await?;
+    // Suspended users' articles disappear from public reads
+    cache::everything_changed(&state);
+
+    Ok(Json(AdminUser {
+        user: user.into_admin_user(),
+    }))
+}
+
+async fn handle_lift_suspension(
+    auth_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> AppJsonResult<AdminUser> {
+    require_admin(&auth_user)?;
+
+    let user = Query::get_user_by_current_username(&state.client, username).await?;
+    let user = Mutation::lift_suspension(&state.client, auth_user.user_id, user.id).await?;
+    cache::everything_changed(&state);
+
+    Ok(Json(AdminUser {
+        user: user.into_admin_user(),
+    }))
+}
+
+async fn handle_force_password_reset(
+    auth_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    require_admin(&auth_user)?;
+
+    let user = Query::get_user_by_current_username(&state.client, username).await?;
+
+    let (token, token_hash) = generate_token();
+    let length = state.config.password_reset_length();
+    let expires_at = Utc::now() + length;
+
+    Mutation::force_password_reset(
+        &state.client,
+        auth_user.user_id,
+        user.id.clone(),
+        token_hash,
+        expires_at.into(),
+    )
+    .await?;
+
+    // The user is signed out by now either way, a failed mail is logged and
+    // the reset can be asked for again from the login page
+    let sent = state
+        .mailer
+        .send(Mail {
+            to: user.email,
+            subject: "Reset your password".into(),
+            body: format!(
+                "An administrator signed your account {} out everywhere.\n\n\
+                Use this token to choose a new password before signing in again, \
+                it expires in {}:\n\n{token}\n",
+                user.username,
+                lifetime(length),
+            ),
+        })
+        .await;
+    if sent.is_err() {
+        error!("Couldn't send the password reset mail to user {}", user.id);
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn handle_impersonate_user(
+    auth_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Impersonation> {
+    require_admin(&auth_user)?;
+
+    let user = Query::get_user_by_current_username(&state.client, username).await?;
+
+    Mutation::audit(
+        &state.client,
+        auth_user.user_id.clone(),
+        "user.impersonate",
+        "user",
+        user.id.clone(),
+        None,
+    )
+    .exec()
+    .await?;
+
+    let (token, expires_at) = AuthUser::impersonation_jwt(&state, user.id, auth_user.user_id);
+
+    Ok(Json(Impersonation {
+        impersonation: ImpersonationBody {
+            username: user.username,
+            token,
+            expires_at: expires_at.into(),
+        },
+    }))
+}
+
+async fn handle_get_audit_log(
+    auth_user: AuthUser,
+    UrlQuery(params): UrlQuery<PageParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<AuditLog> {
+    require_admin(&auth_user)?;
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let entries = Query::get_audit_logs(&state.client, limit, offset)
+        .await?
+        .into_iter()
+        .map(|x| x.into_audit_log_entry())
+        .collect();
+
+    Ok(Json(AuditLog { entries }))
+}
+
+async fn handle_get_cache_stats(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> AppJsonResult<Cache> {
+    require_admin(&auth_user)?;
+
+    let cache = state
+        .cache
+        .as_ref()
+        .map(|x| x.stats())
+        .unwrap_or_default();
+
+    Ok(Json(Cache { cache }))
+}
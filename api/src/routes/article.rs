@@ -1,20 +1,28 @@
 use crate::error::AppError;
 use axum::{
     extract::{Path, Query as UrlQuery, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
+    response::Response,
     routing::{get, post},
     Json, Router,
 };
-use db::{mutation::{Mutation, ArticleToJson}, query::Query};
-use rayon::prelude::*;
+use db::{mutation::Mutation, query::Query};
 
 use crate::{
+    authorization::can_moderate,
+    cache::{self, article_key, LISTS, TAGS},
+    etag::{conditional, conditional_editable, if_match_version, stale_as_precondition, tagged},
+    events::Event,
     extractor::{AuthUser, MaybeAuthUser},
-    util::{check_if_favorited, check_if_following},
-    AppJsonResult, AppState,
+    idempotency::{idempotent, IdempotencyKey},
+    util::ensure_email_verified,
+    AppJsonResult, AppResult, AppState,
 };
 
-use types::article::{Article, NewArticle, Params, UpdateArticle, Tags, MultipleArticles, ArticleBody};
+use types::{
+    article::{Article, NewArticle, Params, UpdateArticle, Tags, MultipleArticles},
+    token::Scope,
+};
 
 pub fn create_routes() -> Router<AppState> {
     Router::new()
@@ -40,130 +48,132 @@ async fn handle_list_articles(
     MaybeAuthUser(maybe_user): MaybeAuthUser,
     UrlQuery(params): UrlQuery<Params>,
     State(state): State<AppState>,
-) -> AppJsonResult<MultipleArticles> {
-    let articles = Query::get_articles(&state.client, params).await?;
-
-    let logged_user = if let Some(logged_user) = maybe_user {
-        Some(Query::get_user_favs_and_follows(&state.client, logged_user.user_id).await?)
-    } else {
-        None
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    // Only the first page is cached, later ones are read far less often
+    let key = match (&state.cache, &maybe_user, params.offset) {
+        (Some(_), None, None | Some(0)) => Some(format!(
+            "{LISTS}{}|{}|{}|{}",
+            params.tag.as_deref().unwrap_or_default(),
+            params.author.as_deref().unwrap_or_default(),
+            params.favorited.as_deref().unwrap_or_default(),
+            params.limit.unwrap_or_default(),
+        )),
+        _ => None,
     };
+    if let (Some(cache), Some(key)) = (&state.cache, &key) {
+        if let Some(articles) = cache.get::<MultipleArticles>(key) {
+            return Ok(conditional(&headers, articles));
+        }
+    }
 
-    let articles: Vec<ArticleBody> = articles
-        .into_par_iter()
-        .map(|x| {
-            let (is_favorited, is_following) = if let Some(logged_user) = &logged_user {
-                let (favorites, follows) = (
-                    logged_user
-                        .favorites
-                        .par_iter()
-                        .map(|x| x.id.as_str())
-                        .collect::<Vec<&str>>(),
-                    logged_user
-                        .follows
-                        .par_iter()
-                        .map(|x| x.id.as_str())
-                        .collect::<Vec<&str>>(),
-                );
-
-                (
-                    check_if_favorited(&favorites, &x.id),
-                    check_if_following(&follows, &x.user_id),
-                )
-            } else {
-                (false, false)
-            };
-
-            x.into_article_body(is_following, is_favorited)
-        })
-        .collect();
+    let viewer = maybe_user.map(|x| x.user_id);
+    let articles = Query::get_articles(&state.client, params, viewer).await?;
 
-    Ok(Json(MultipleArticles {
+    let articles = MultipleArticles {
         articles_count: articles.len() as i32,
         articles
-    }))
+    };
+    if let (Some(cache), Some(key)) = (&state.cache, key) {
+        cache.insert(key, articles.clone());
+    }
+
+    Ok(conditional(&headers, articles))
 }
 
 async fn handle_get_article(
     MaybeAuthUser(maybe_user): MaybeAuthUser,
     Path(slug): Path<String>,
     State(state): State<AppState>,
-) -> AppJsonResult<Article> {
-    let article = Query::get_article_by_slug(&state.client, slug).await?;
-
-    let logged_user = if let Some(logged_user) = maybe_user {
-        Some(Query::get_user_favs_and_follows(&state.client, logged_user.user_id).await?)
-    } else {
-        None
-    };
-
-    let is_favorited = if let Some(logged_user) = &logged_user {
-        let favorites = logged_user
-            .favorites
-            .par_iter()
-            .map(|x| x.id.as_str())
-            .collect::<Vec<&str>>();
-        check_if_favorited(&favorites, &article.id)
-    } else {
-        false
-    };
-
-    let is_following = if let Some(logged_user) = &logged_user {
-        let follows = logged_user
-            .follows
-            .par_iter()
-            .map(|x| x.id.as_str())
-            .collect::<Vec<&str>>();
-        check_if_following(&follows, &article.user.id)
-    } else {
-        false
-    };
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let article = get_article(&state, maybe_user, slug).await?;
+    Ok(conditional_editable(&headers, article))
+}
 
-    Ok(Json(article.into_article(is_following, is_favorited)))
+async fn get_article(
+    state: &AppState,
+    maybe_user: Option<AuthUser>,
+    slug: String,
+) -> AppResult<Article> {
+    let cache = state.cache.as_ref().filter(|_| maybe_user.is_none());
+    if let Some(article) = cache.and_then(|x| x.get::<Article>(&article_key(&slug))) {
+        return Ok(article);
+    }
+
+    let article = Query::get_article_by_slug(&state.client, slug.clone()).await?;
+
+    // Hidden articles are only shown to their author and moderators
+    if article.hidden_at.is_some() {
+        let allowed = maybe_user
+            .as_ref()
+            .map(|x| x.user_id == article.user.id || can_moderate(x))
+            .unwrap_or(false);
+        if !allowed {
+            return Err(AppError::NotFound);
+        }
+    }
+
+    let viewer = maybe_user.map(|x| x.user_id);
+    let article = Query::article_for_viewer(&state.client, article, viewer).await?;
+
+    // Hidden articles never got this far for anonymous readers
+    if let Some(cache) = cache {
+        cache.insert(article_key(&slug), article.clone());
+    }
+
+    Ok(article)
 }
 
 async fn handle_create_article(
     auth_user: AuthUser,
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(input): Json<NewArticle>,
+) -> AppResult<Response> {
+    auth_user.require_scope(Scope::ArticlesWrite)?;
+    ensure_email_verified(&state, &auth_user.user_id).await?;
+
+    let key = IdempotencyKey::from_request(&headers, &auth_user.user_id, "/api/articles", &input)?;
+    idempotent(&state, key, create_article(state.clone(), auth_user, input)).await
+}
+
+async fn create_article(
+    state: AppState,
+    auth_user: AuthUser,
+    input: NewArticle,
 ) -> AppJsonResult<Article> {
-    let article = Mutation::create_article(&state.client, input, auth_user.user_id).await?;
-
-    let is_following = check_if_following(
-        &article
-            .user
-            .follows
-            .par_iter()
-            .map(|x| x.id.as_str())
-            .collect::<Vec<&str>>(),
-        &article.user.id,
-    );
-
-    Ok(Json(article.into_article(is_following, false)))
+    let (article, notifications) =
+        Mutation::create_article(&state.client, input, auth_user.user_id.clone()).await?;
+    let author_id = article.user.id.clone();
+    cache::article_changed(&state, &article.slug);
+    cache::tags_changed(&state);
+
+    let article = Query::article_for_viewer(&state.client, article, Some(auth_user.user_id)).await?;
+
+    // Whoever gets it in their feed follows the author
+    let mut feed_article = article.article.clone();
+    feed_article.author.profile.following = true;
+    state
+        .events
+        .publish(Event::Article { author_id, article: feed_article })
+        .await;
+    for notification in notifications {
+        state.events.publish(Event::notification(notification)).await;
+    }
+
+    Ok(Json(article))
 }
 
 pub async fn handle_feed_articles(
-    AuthUser { user_id }: AuthUser,
+    auth_user: AuthUser,
     UrlQuery(params): UrlQuery<Params>,
     State(state): State<AppState>,
 ) -> AppJsonResult<MultipleArticles> {
-    let articles = Query::get_followed_articles(&state.client, user_id.clone(), params).await?;
+    auth_user.require_scope(Scope::Read)?;
+    let user_id = auth_user.user_id;
 
-    let user = Query::get_user_favs_and_follows(&state.client, user_id).await?;
-
-    let favorites = user
-        .favorites
-        .par_iter()
-        .map(|x| x.id.as_str())
-        .collect::<Vec<&str>>();
-
-    let articles: Vec<ArticleBody> = articles
-        .into_iter()
-        .map(|x| {
-            let favorited = check_if_favorited(&favorites, &x.id);
-            x.into_article_body(true, favorited)
-        })
-        .collect();
+    let articles = Query::get_followed_articles(&state.client, user_id, params).await?;
 
     Ok(Json(MultipleArticles {
         articles_count: articles.len() as i32,
@@ -172,42 +182,51 @@ pub async fn handle_feed_articles(
 }
 
 pub async fn handle_update_article(
-    AuthUser { user_id }: AuthUser,
+    auth_user: AuthUser,
     Path(slug): Path<String>,
     State(state): State<AppState>,
-    Json(input): Json<UpdateArticle>,
-) -> AppJsonResult<Article> {
-    let article = Mutation::update_article(&state.client, input, slug, user_id).await?;
-
-    let is_favorited = check_if_favorited(
-        &article
-            .user
-            .favorites
-            .par_iter()
-            .map(|x| x.id.as_str())
-            .collect::<Vec<&str>>(),
-        &article.id,
-    );
-
-    let is_following = check_if_following(
-        &article
-            .user
-            .follows
-            .par_iter()
-            .map(|x| x.id.as_str())
-            .collect::<Vec<&str>>(),
-        &article.user.id,
-    );
-
-    Ok(Json(article.into_article(is_following, is_favorited)))
+    headers: HeaderMap,
+    Json(mut input): Json<UpdateArticle>,
+) -> AppResult<Response> {
+    auth_user.require_scope(Scope::ArticlesWrite)?;
+    if input.article.clears_required() {
+        return Err(AppError::Unprocessable);
+    }
+
+    let if_match = if_match_version(&headers)?;
+    input.article.version = if_match.or(input.article.version);
+
+    let moderator = can_moderate(&auth_user);
+    let (article, notifications) =
+        Mutation::update_article(&state.client, input, slug.clone(), auth_user.user_id.clone(), moderator)
+            .await
+            .map_err(stale_as_precondition(if_match))?;
+
+    // A new title moves the article to a new slug
+    cache::article_changed(&state, &slug);
+    cache::article_changed(&state, &article.slug);
+    cache::tags_changed(&state);
+
+    for notification in notifications {
+        state.events.publish(Event::notification(notification)).await;
+    }
+
+    let article = Query::article_for_viewer(&state.client, article, Some(auth_user.user_id)).await?;
+
+    Ok(tagged(article))
 }
 
 pub async fn handle_delete_article(
-    AuthUser { user_id }: AuthUser,
+    auth_user: AuthUser,
     Path(slug): Path<String>,
     State(state): State<AppState>,
 ) -> Result<StatusCode, AppError> {
-    Mutation::delete_article(&state.client, slug, user_id).await?;
+    auth_user.require_scope(Scope::ArticlesWrite)?;
+
+    let moderator = can_moderate(&auth_user);
+    Mutation::delete_article(&state.client, slug.clone(), auth_user.user_id, moderator).await?;
+    cache::article_changed(&state, &slug);
+    cache::tags_changed(&state);
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -217,9 +236,18 @@ pub async fn handle_favorite_article(
     Path(slug): Path<String>,
     State(state): State<AppState>,
 ) -> AppJsonResult<Article> {
-    Mutation::favorite_unfavorite_article(&state.client, slug.clone(), auth_user.user_id.clone(), true).await?;
+    auth_user.require_scope(Scope::ArticlesWrite)?;
+
+    let (_, notification) =
+        Mutation::favorite_unfavorite_article(&state.client, slug.clone(), auth_user.user_id.clone(), true).await?;
+    cache::article_changed(&state, &slug);
+
+    if let Some(notification) = notification {
+        state.events.publish(Event::notification(notification)).await;
+    }
 
-    handle_get_article(MaybeAuthUser(Some(auth_user)), Path(slug), State(state)).await
+    let article = get_article(&state, Some(auth_user), slug).await?;
+    Ok(Json(article))
 }
 
 pub async fn handle_unfavorite_article(
@@ -227,12 +255,24 @@ pub async fn handle_unfavorite_article(
     Path(slug): Path<String>,
     State(state): State<AppState>,
 ) -> AppJsonResult<Article> {
+    auth_user.require_scope(Scope::ArticlesWrite)?;
+
     Mutation::favorite_unfavorite_article(&state.client, slug.clone(), auth_user.user_id.clone(), false).await?;
+    cache::article_changed(&state, &slug);
 
-    handle_get_article(MaybeAuthUser(Some(auth_user)), Path(slug), State(state)).await
+    let article = get_article(&state, Some(auth_user), slug).await?;
+    Ok(Json(article))
 }
 
-pub async fn handle_get_tags(State(state): State<AppState>) -> AppJsonResult<Tags> {
+pub async fn handle_get_tags(State(state): State<AppState>, headers: HeaderMap) -> AppResult<Response> {
+    if let Some(tags) = state.cache.as_ref().and_then(|x| x.get::<Tags>(TAGS)) {
+        return Ok(conditional(&headers, tags));
+    }
+
     let tags = Query::get_tags(&state.client).await?;
-    Ok(Json(tags))
+    if let Some(cache) = &state.cache {
+        cache.insert(TAGS.to_string(), tags.clone());
+    }
+
+    Ok(conditional(&headers, tags))
 }
\ No newline at end of file
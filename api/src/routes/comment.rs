@@ -1,19 +1,25 @@
 use axum::{
     extract::{Path, State},
     routing::{post, delete},
-    Json, Router, http::StatusCode,
+    Json, Router, http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use types::{
+    comment::{Comment, NewComment, Comments},
+    token::Scope,
 };
-use types::comment::{Comment, NewComment, Comments, CommentBody};
 use db::{mutation::Mutation, query::Query};
 
 use crate::{
+    authorization::can_moderate,
+    cache,
+    events::Event,
     extractor::{AuthUser, MaybeAuthUser},
-    util::check_if_following,
-    AppJsonResult, AppState, error::AppError,
+    idempotency::{idempotent, IdempotencyKey},
+    util::ensure_email_verified,
+    AppJsonResult, AppResult, AppState, error::AppError,
 };
 
-use rayon::prelude::*;
-
 pub fn create_routes() -> Router<AppState> {
     Router::new()
     .route(
@@ -24,19 +30,55 @@ pub fn create_routes() -> Router<AppState> {
 }
 
 async fn handle_create_comment(
-    AuthUser { user_id }: AuthUser,
+    auth_user: AuthUser,
     Path(slug): Path<String>,
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(input): Json<NewComment>,
+) -> AppResult<Response> {
+    auth_user.require_scope(Scope::CommentsWrite)?;
+    let user_id = auth_user.user_id;
+
+    ensure_email_verified(&state, &user_id).await?;
+
+    let path = format!("/api/articles/{slug}/comments");
+    let key = IdempotencyKey::from_request(&headers, &user_id, &path, &input)?;
+    idempotent(&state, key, create_comment(state.clone(), user_id, slug, input)).await
+}
+
+async fn create_comment(
+    state: AppState,
+    user_id: String,
+    slug: String,
+    input: NewComment,
 ) -> AppJsonResult<Comment> {
-    let comment = Mutation::create_comment(&state.client, input, slug, user_id.clone()).await?;
+    // Authors who blocked the user don't get comments from them
+    let article = Query::get_article_by_slug(&state.client, slug.clone()).await?;
+    if Query::is_blocked(&state.client, article.user.id, user_id.clone()).await? {
+        return Err(AppError::Forbidden);
+    }
 
-    let is_following = {
-        let follows = Query::get_user_follows_by_id(&state.client, user_id).await?;
-        check_if_following(&follows, &comment.author.id)
-    };
+    let (comment, notifications) =
+        Mutation::create_comment(&state.client, input, slug.clone(), user_id.clone()).await?;
+    cache::article_changed(&state, &slug);
 
-    Ok(Json(comment.into_comment(is_following)))
+    let author_id = comment.author.id.clone();
+    let flags =
+        Query::get_viewer_flags(&state.client, Some(user_id), vec![], vec![author_id.clone()])
+            .await?;
+    let is_following = flags.following(&author_id);
+
+    let comment = comment.into_comment(is_following);
+
+    state
+        .events
+        .publish(Event::Comment { slug, author_id, comment: comment.comment.clone() })
+        .await;
+    for notification in notifications {
+        state.events.publish(Event::notification(notification)).await;
+    }
+
+    Ok(Json(comment))
 }
 
 async fn handle_comments_from_article(
@@ -44,40 +86,26 @@ async fn handle_comments_from_article(
     Path(slug): Path<String>,
     State(state): State<AppState>,
 ) -> AppJsonResult<Comments> {
-    let comments = Query::get_comments_from_article(&state.client, slug).await?;
-
-    let logged_user = if let Some(logged_user) = maybe_user {
-        Some(Query::get_user_favs_and_follows(&state.client, logged_user.user_id).await?)
-    } else {
-        None
-    };
-
-    let comments: Vec<CommentBody> = comments
-        .into_par_iter()
-        .map(|x| {
-            let is_following = if let Some(logged_user) = &logged_user {
-                let follows = logged_user
-                    .follows
-                    .par_iter()
-                    .map(|x| x.id.as_str())
-                    .collect::<Vec<&str>>();
-                check_if_following(&follows, &x.user_id)
-            } else {
-                false
-            };
-
-            x.into_comment_body(is_following)
-        })
-        .collect();
+    let viewer = maybe_user.map(|x| x.user_id);
+    let comments = Query::get_comments_from_article(&state.client, slug, viewer).await?;
 
     Ok(Json(Comments { comments }))
 }
 
 async fn handle_delete_comment(
-    AuthUser { user_id }: AuthUser,
-    Path((_slug, id)): Path<(String, String)>,
+    auth_user: AuthUser,
+    Path((slug, id)): Path<(String, String)>,
     State(state): State<AppState>
 ) -> Result<StatusCode, AppError> {
-    Mutation::delete_comment(&state.client, id, user_id).await?;
+    auth_user.require_scope(Scope::CommentsWrite)?;
+
+    // The slug picks the cached article to refresh, so it has to be the comment's
+    if !Query::is_comment_on_article(&state.client, id.clone(), slug.clone()).await? {
+        return Err(AppError::NotFound);
+    }
+
+    let moderator = can_moderate(&auth_user);
+    Mutation::delete_comment(&state.client, id, auth_user.user_id, moderator).await?;
+    cache::article_changed(&state, &slug);
     Ok(StatusCode::NO_CONTENT)
 }
\ No newline at end of file
@@ -0,0 +1,57 @@
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+
+use db::query::Query;
+
+use crate::AppState;
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(handle_health))
+        .route("/readyz", get(handle_ready))
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    database: &'static str,
+    build: Build,
+}
+
+#[derive(Serialize)]
+struct Build {
+    version: &'static str,
+    /// Set through `GIT_COMMIT` when building
+    commit: Option<&'static str>,
+}
+
+// Only says the process is up, a slow database shouldn't get it restarted
+async fn handle_health() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+// Load balancers should stop sending traffic once shutdown starts, or while the database is away
+async fn handle_ready(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let database = match Query::ping(&state.client).await {
+        Ok(_) => "ok",
+        Err(_) => "unavailable",
+    };
+    let ready = database == "ok" && !state.shutdown.is_triggered();
+
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "unavailable" },
+        database,
+        build: Build {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: option_env!("GIT_COMMIT"),
+        },
+    };
+
+    (status, Json(readiness))
+}
@@ -0,0 +1,173 @@
+use axum::{
+    extract::{Json, State},
+    http::{header::SET_COOKIE, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::{Duration, Utc};
+
+use db::{mutation::Mutation, query::Query, DbErr};
+
+use crate::{
+    error::AppError,
+    extractor::MaybeAuthUser,
+    hashing::{generate_token, hash_password},
+    oidc::UserInfo,
+    routes::user::session_or_challenge,
+    util::cookie,
+    AppResult, AppState,
+};
+
+use types::user::{OidcAuthorization, OidcCallback};
+
+// Ties a login to the browser that started it, so nobody can make someone
+// else's browser finish their login, or their account linking
+const STATE_COOKIE: &str = "oidc_state";
+const LOGIN_MINUTES: i64 = 10;
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/auth/oidc/authorize", get(handle_oidc_authorize))
+        .route("/api/auth/oidc/callback", post(handle_oidc_callback))
+}
+
+// Called while logged in, this links the provider account to the current user instead
+async fn handle_oidc_authorize(
+    MaybeAuthUser(maybe_user): MaybeAuthUser,
+    State(state): State<AppState>,
+) -> AppResult<Response> {
+    let provider = state.oidc.as_ref().ok_or(AppError::NotFound)?;
+
+    let link_user_id = match maybe_user {
+        Some(user) => {
+            user.require_session()?;
+            Some(user.user_id)
+        }
+        None => None,
+    };
+
+    let (login_state, _) = generate_token();
+    let (code_verifier, _) = generate_token();
+    let expires_at = Utc::now() + Duration::minutes(LOGIN_MINUTES);
+
+    let authorization_url = provider
+        .authorization_url(&login_state, &code_verifier)
+        .await?;
+
+    let cookie = state_cookie(&login_state, LOGIN_MINUTES * 60);
+
+    Mutation::create_oidc_login(
+        &state.client,
+        login_state,
+        code_verifier,
+        link_user_id,
+        expires_at.into(),
+    )
+    .await?;
+
+    Ok(([(SET_COOKIE, cookie)], Json(OidcAuthorization { authorization_url })).into_response())
+}
+
+async fn handle_oidc_callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(input): Json<OidcCallback>,
+) -> AppResult<Response> {
+    let provider = state.oidc.as_ref().ok_or(AppError::NotFound)?;
+
+    if cookie(&headers, STATE_COOKIE) != Some(input.state.as_str()) {
+        return Err(AppError::Unathorized);
+    }
+
+    let login = Mutation::take_oidc_login(&state.client, input.state).await?;
+    let info = provider.exchange(&input.code, &login.code_verifier).await?;
+
+    let identity =
+        Query::get_identity(&state.client, provider.issuer.clone(), info.sub.clone()).await?;
+
+    let user = match (identity, login.link_user_id) {
+        (Some(identity), None) => identity.user,
+        (Some(identity), Some(link_user_id)) if identity.user_id == link_user_id => identity.user,
+        // Already linked to somebody else
+        (Some(_), Some(_)) => return Err(AppError::Conflict),
+        (None, Some(link_user_id)) => {
+            Mutation::create_identity(
+                &state.client,
+                link_user_id.clone(),
+                provider.issuer.clone(),
+                info.sub,
+                info.email,
+            )
+            .await?;
+            Query::get_user_by_id(&state.client, link_user_id).await?
+        }
+        (None, None) => create_user_from_provider(&state, provider.issuer.clone(), info).await?,
+    };
+
+    let mut response = session_or_challenge(&state, user).await?;
+    response
+        .headers_mut()
+        .insert(SET_COOKIE, state_cookie("", 0));
+
+    Ok(response)
+}
+
+fn state_cookie(value: &str, max_age: i64) -> HeaderValue {
+    let cookie = format!(
+        "{STATE_COOKIE}={value}; Path=/api/auth/oidc; Max-Age={max_age}; HttpOnly; SameSite=Lax"
+    );
+    HeaderValue::from_str(&cookie).expect("Login states are hex")
+}
+
+async fn create_user_from_provider(
+    state: &AppState,
+    issuer: String,
+    info: UserInfo,
+) -> AppResult<db::prisma::user::Data> {
+    let email = info.email.ok_or(AppError::BadRequest)?;
+
+    // Existing accounts have to log in with their password and link the provider
+    // themselves, or anyone controlling that address at the provider could take them over.
+    match Query::get_user_by_email(&state.client, email.clone()).await {
+        Ok(_) => return Err(AppError::Conflict),
+        Err(DbErr::NotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let username = available_username(state, info.preferred_username.as_deref().unwrap_or(&email)).await?;
+    let password = hash_password(generate_token().0).await?;
+
+    let user = Mutation::create_oidc_user(
+        &state.client,
+        username,
+        email,
+        password,
+        info.email_verified,
+        issuer,
+        info.sub,
+    )
+    .await?;
+
+    Ok(user)
+}
+
+async fn available_username(state: &AppState, wanted: &str) -> AppResult<String> {
+    let base: String = wanted
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|x| x.is_alphanumeric() || *x == '_' || *x == '-')
+        .collect();
+    let base = if base.is_empty() { "user".to_string() } else { base };
+
+    let mut username = base.clone();
+    loop {
+        match Query::get_user_by_username(&state.client, username.clone()).await {
+            Err(DbErr::NotFound) => return Ok(username),
+            Err(e) => return Err(e.into()),
+            Ok(_) => username = format!("{base}-{}", &generate_token().0[..6]),
+        }
+    }
+}
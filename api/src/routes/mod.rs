@@ -1,4 +1,12 @@
 pub mod user;
 pub mod article;
 pub mod profile;
-pub mod comment;
\ No newline at end of file
+pub mod comment;
+pub mod two_factor;
+pub mod token;
+pub mod identity;
+pub mod admin;
+pub mod report;
+pub mod notification;
+pub mod stream;
+pub mod health;
@@ -0,0 +1,122 @@
+use axum::{
+    extract::{Json, Path, Query as UrlQuery, State},
+    http::StatusCode,
+    routing::{get, post},
+    Router,
+};
+
+use db::{mutation::Mutation, query::Query};
+
+use crate::{error::AppError, extractor::AuthUser, AppJsonResult, AppState};
+
+use types::{
+    notification::{
+        NotificationParams, NotificationSettings, NotificationSettingsBody, Notifications,
+    },
+    token::Scope,
+};
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/notifications", get(handle_get_notifications))
+        .route("/api/notifications/read", post(handle_mark_all_read))
+        .route("/api/notifications/:id/read", post(handle_mark_read))
+        .route(
+            "/api/notifications/settings",
+            get(handle_get_settings).put(handle_update_settings),
+        )
+}
+
+async fn handle_get_notifications(
+    auth_user: AuthUser,
+    UrlQuery(params): UrlQuery<NotificationParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Notifications> {
+    auth_user.require_scope(Scope::Read)?;
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let (notifications, unread_count) = Query::get_notifications(
+        &state.client,
+        auth_user.user_id,
+        params.unread,
+        limit,
+        offset,
+    )
+    .await?;
+
+    Ok(Json(Notifications {
+        notifications: notifications
+            .into_iter()
+            .map(|x| x.into_notification_body())
+            .collect(),
+        unread_count,
+    }))
+}
+
+async fn handle_mark_read(
+    auth_user: AuthUser,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    auth_user.require_session()?;
+
+    Mutation::mark_notification_read(&state.client, auth_user.user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn handle_mark_all_read(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    auth_user.require_session()?;
+
+    Mutation::mark_all_notifications_read(&state.client, auth_user.user_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn handle_get_settings(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> AppJsonResult<NotificationSettings> {
+    auth_user.require_session()?;
+
+    let muted = Query::get_muted_notifications(&state.client, auth_user.user_id).await?;
+
+    Ok(Json(NotificationSettings {
+        settings: NotificationSettingsBody {
+            muted: muted.into_iter().map(Into::into).collect(),
+        },
+    }))
+}
+
+async fn handle_update_settings(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+    Json(input): Json<NotificationSettings>,
+) -> AppJsonResult<NotificationSettings> {
+    auth_user.require_session()?;
+
+    let mut muted = Vec::new();
+    for kind in input.settings.muted {
+        if !muted.contains(&kind) {
+            muted.push(kind);
+        }
+    }
+
+    let muted = Mutation::set_muted_notifications(
+        &state.client,
+        auth_user.user_id,
+        muted.into_iter().map(Into::into).collect(),
+    )
+    .await?;
+
+    Ok(Json(NotificationSettings {
+        settings: NotificationSettingsBody {
+            muted: muted.into_iter().map(Into::into).collect(),
+        },
+    }))
+}
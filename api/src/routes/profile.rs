@@ -1,38 +1,161 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query as UrlQuery, State},
+    http::HeaderMap,
+    response::{IntoResponse, Redirect, Response},
     routing::{get, post},
     Router, Json,
 };
-use db::{query::Query, mutation::Mutation};
+use db::{query::{avatar, Query}, mutation::Mutation, prisma::user::Data as UserData};
 use crate::{
+    error::AppError,
+    etag::conditional,
+    events::Event,
     extractor::{AuthUser, MaybeAuthUser},
-    AppJsonResult, AppState, util::check_if_following
+    AppJsonResult, AppResult, AppState, util::percent_encode
 };
 
-use types::user::Profile;
+use types::{
+    admin::{PageParams, UserSearchParams},
+    user::{Profile, ProfileSearch, Profiles, Suggestion, Suggestions},
+};
 
 pub fn create_routes() -> Router<AppState> {
     Router::new()
+        .route("/api/profiles", get(handle_search_profiles))
+        .route("/api/profiles/autocomplete", get(handle_autocomplete))
         .route("/api/profiles/:username", get(handle_get_profile))
+        .route("/api/profiles/:username/followers", get(handle_get_followers))
+        .route("/api/profiles/:username/following", get(handle_get_following))
         .route("/api/profiles/:username/follow", post(handle_follow_user).delete(handle_unfollow_user))
+        .route("/api/profiles/:username/block", post(handle_block_user).delete(handle_unblock_user))
+        .route("/api/profiles/:username/mute", post(handle_mute_user).delete(handle_unmute_user))
+        .route("/api/user/blocks", get(handle_get_blocked_users))
+        .route("/api/user/mutes", get(handle_get_muted_users))
 }
 
 async fn handle_get_profile(
     MaybeAuthUser(maybe_user): MaybeAuthUser,
     Path(username): Path<String>,
     State(state): State<AppState>,
-) -> AppJsonResult<Profile> {
-    let user = Query::get_user_by_username(&state.client, username).await?;
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let user = Query::get_user_by_username(&state.client, username.clone()).await?;
+
+    // Links with an old username get sent to the current profile
+    if user.username != username {
+        // Temporary, the old name can be taken by someone else once the cooldown is over
+        let location = format!("/api/profiles/{}", percent_encode(&user.username));
+        return Ok(Redirect::temporary(&location).into_response());
+    }
 
     let following = if let Some(logged_user) = maybe_user 
     {
-        let follows = Query::get_user_follows_by_id(&state.client, logged_user.user_id).await?;
-        check_if_following(&follows, &user.id)
+        ensure_not_blocked(&state, &user, &logged_user.user_id).await?;
+
+        is_following(&state, logged_user.user_id, &user.id).await?
     } else {
         false
     };
 
-    Ok(Json(user.into_profile(following)))
+    let stats = Query::get_profile_stats(&state.client, user.id.clone()).await?;
+
+    let mut profile = user.into_profile(following);
+    profile.profile.stats = Some(stats);
+
+    Ok(conditional(&headers, profile))
+}
+
+async fn handle_search_profiles(
+    MaybeAuthUser(maybe_user): MaybeAuthUser,
+    UrlQuery(params): UrlQuery<UserSearchParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<ProfileSearch> {
+    let q = params.q.unwrap_or_default();
+    let viewer = maybe_user.map(|x| x.user_id);
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    if q.trim().is_empty() {
+        return Ok(Json(ProfileSearch { profiles: vec![], profiles_count: 0 }));
+    }
+
+    let (users, profiles_count) =
+        Query::search_profiles(&state.client, q.trim(), viewer.clone(), false, limit, offset)
+            .await?;
+
+    let Json(Profiles { profiles }) = into_profiles(&state, viewer, users).await?;
+
+    Ok(Json(ProfileSearch { profiles, profiles_count }))
+}
+
+// Only matches the start of usernames, for mention pickers
+async fn handle_autocomplete(
+    MaybeAuthUser(maybe_user): MaybeAuthUser,
+    UrlQuery(params): UrlQuery<UserSearchParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Suggestions> {
+    let q = params.q.unwrap_or_default();
+    let limit = params.limit.unwrap_or(10).clamp(1, 20);
+
+    if q.trim().is_empty() {
+        return Ok(Json(Suggestions { suggestions: vec![] }));
+    }
+
+    let viewer = maybe_user.map(|x| x.user_id);
+    let (users, _) =
+        Query::search_profiles(&state.client, q.trim(), viewer, true, limit, 0).await?;
+
+    let suggestions = users
+        .into_iter()
+        .map(|x| Suggestion { username: x.username, image: avatar(x.image) })
+        .collect();
+
+    Ok(Json(Suggestions { suggestions }))
+}
+
+async fn handle_get_followers(
+    MaybeAuthUser(maybe_user): MaybeAuthUser,
+    Path(username): Path<String>,
+    UrlQuery(params): UrlQuery<PageParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profiles> {
+    follow_list(maybe_user, username, params, state, true).await
+}
+
+async fn handle_get_following(
+    MaybeAuthUser(maybe_user): MaybeAuthUser,
+    Path(username): Path<String>,
+    UrlQuery(params): UrlQuery<PageParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profiles> {
+    follow_list(maybe_user, username, params, state, false).await
+}
+
+async fn follow_list(
+    maybe_user: Option<AuthUser>,
+    username: String,
+    params: PageParams,
+    state: AppState,
+    followers: bool,
+) -> AppJsonResult<Profiles> {
+    let user = Query::get_user_by_username(&state.client, username).await?;
+
+    let viewer = maybe_user.map(|x| x.user_id);
+    if let Some(viewer) = &viewer {
+        ensure_not_blocked(&state, &user, viewer).await?;
+    }
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    let users = if followers {
+        Query::get_followers(&state.client, user.id, limit, offset).await?
+    } else {
+        Query::get_following(&state.client, user.id, limit, offset).await?
+    };
+
+    into_profiles(&state, viewer, users).await
 }
 
 async fn handle_follow_user(
@@ -40,15 +163,21 @@ async fn handle_follow_user(
     Path(username): Path<String>,
     State(state): State<AppState>,
 ) -> AppJsonResult<Profile> {
+    logged_user.require_session()?;
+
     let user = Query::get_user_by_username(&state.client, username).await?;
 
-    let follows = Mutation::follow_unfollow_user(
+    ensure_not_blocked(&state, &user, &logged_user.user_id).await?;
+
+    let notification = Mutation::follow_unfollow_user(
         &state.client, logged_user.user_id, user.id.clone(), true)
         .await?;
 
-    let following = check_if_following(&follows, &user.id);
+    if let Some(notification) = notification {
+        state.events.publish(Event::notification(notification)).await;
+    }
 
-    Ok(Json(user.into_profile(following)))
+    Ok(Json(user.into_profile(true)))
 }
 
 async fn handle_unfollow_user(
@@ -56,13 +185,135 @@ async fn handle_unfollow_user(
     Path(username): Path<String>,
     State(state): State<AppState>,
 ) -> AppJsonResult<Profile> {
+    logged_user.require_session()?;
+
     let user = Query::get_user_by_username(&state.client, username).await?;
 
-    let follows = Mutation::follow_unfollow_user(
+    Mutation::follow_unfollow_user(
         &state.client, logged_user.user_id, user.id.clone(), false)
         .await?;
 
-    let following = check_if_following(&follows, &user.id);
+    Ok(Json(user.into_profile(false)))
+}
+
+// To a blocked user, the blocker's profile looks like it doesn't exist
+async fn ensure_not_blocked(state: &AppState, user: &UserData, viewer_id: &str) -> AppResult<()> {
+    if Query::is_blocked(&state.client, user.id.clone(), viewer_id.to_string()).await? {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(())
+}
+
+async fn handle_block_user(
+    logged_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profile> {
+    block_or_mute(logged_user, username, state, Relation::Block, true).await
+}
+
+async fn handle_unblock_user(
+    logged_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profile> {
+    block_or_mute(logged_user, username, state, Relation::Block, false).await
+}
+
+async fn handle_mute_user(
+    logged_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profile> {
+    block_or_mute(logged_user, username, state, Relation::Mute, true).await
+}
+
+async fn handle_unmute_user(
+    logged_user: AuthUser,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profile> {
+    block_or_mute(logged_user, username, state, Relation::Mute, false).await
+}
+
+enum Relation {
+    Block,
+    Mute,
+}
+
+async fn block_or_mute(
+    logged_user: AuthUser,
+    username: String,
+    state: AppState,
+    relation: Relation,
+    enable: bool,
+) -> AppJsonResult<Profile> {
+    logged_user.require_session()?;
+
+    let user = Query::get_user_by_username(&state.client, username).await?;
+
+    if user.id == logged_user.user_id {
+        return Err(AppError::BadRequest);
+    }
+
+    let (user_id, target_id) = (logged_user.user_id.clone(), user.id.clone());
+    match relation {
+        Relation::Block => {
+            Mutation::block_unblock_user(&state.client, user_id, target_id, enable).await?
+        }
+        Relation::Mute => {
+            Mutation::mute_unmute_user(&state.client, user_id, target_id, enable).await?
+        }
+    }
+
+    let following = is_following(&state, logged_user.user_id, &user.id).await?;
 
     Ok(Json(user.into_profile(following)))
 }
+
+async fn handle_get_blocked_users(
+    logged_user: AuthUser,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profiles> {
+    logged_user.require_session()?;
+
+    let users = Query::get_blocked_users(&state.client, logged_user.user_id.clone()).await?;
+
+    into_profiles(&state, Some(logged_user.user_id), users).await
+}
+
+async fn handle_get_muted_users(
+    logged_user: AuthUser,
+    State(state): State<AppState>,
+) -> AppJsonResult<Profiles> {
+    logged_user.require_session()?;
+
+    let users = Query::get_muted_users(&state.client, logged_user.user_id.clone()).await?;
+
+    into_profiles(&state, Some(logged_user.user_id), users).await
+}
+
+async fn into_profiles(state: &AppState, viewer: Option<String>, users: Vec<UserData>) -> AppJsonResult<Profiles> {
+    let user_ids = users.iter().map(|x| x.id.clone()).collect();
+    let flags = Query::get_viewer_flags(&state.client, viewer, vec![], user_ids).await?;
+
+    let profiles = users
+        .into_iter()
+        .map(|x| {
+            let following = flags.following(&x.id);
+            x.into_profile(following).profile
+        })
+        .collect();
+
+    Ok(Json(Profiles { profiles }))
+}
+
+// Asks about just this user, rather than loading everyone the viewer follows
+async fn is_following(state: &AppState, viewer: String, user_id: &str) -> AppResult<bool> {
+    let flags =
+        Query::get_viewer_flags(&state.client, Some(viewer), vec![], vec![user_id.to_string()])
+            .await?;
+
+    Ok(flags.following(user_id))
+}
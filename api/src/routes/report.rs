@@ -0,0 +1,170 @@
+use axum::{
+    extract::{Json, Path, Query as UrlQuery, State},
+    http::StatusCode,
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+
+use db::{
+    mutation::{Mutation, ReportTarget},
+    prisma::{ReportStatus, Role as DbRole},
+    query::Query,
+};
+
+use crate::{
+    authorization::require_moderator, cache, error::AppError, extractor::AuthUser, AppJsonResult,
+    AppState,
+};
+
+use types::{
+    report::{NewReport, Report, ReportAction, ReportParams, ReportResolution, Reports},
+    user::Role,
+};
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/articles/:slug/report", post(handle_report_article))
+        .route(
+            "/api/articles/:slug/comments/:id/report",
+            post(handle_report_comment),
+        )
+        .route("/api/admin/reports", get(handle_get_reports))
+        .route("/api/admin/reports/:id/resolve", post(handle_resolve_report))
+}
+
+async fn handle_report_article(
+    auth_user: AuthUser,
+    Path(slug): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<NewReport>,
+) -> AppJsonResult<Report> {
+    // Reports count toward hiding content, so they need the user themselves
+    auth_user.require_session()?;
+
+    let article = Query::get_article_by_slug(&state.client, slug).await?;
+
+    report(&state, auth_user, input, ReportTarget::Article(article.id)).await
+}
+
+async fn handle_report_comment(
+    auth_user: AuthUser,
+    Path((slug, id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Json(input): Json<NewReport>,
+) -> AppJsonResult<Report> {
+    auth_user.require_session()?;
+
+    if !Query::is_comment_on_article(&state.client, id.clone(), slug).await? {
+        return Err(AppError::NotFound);
+    }
+
+    report(&state, auth_user, input, ReportTarget::Comment(id)).await
+}
+
+async fn report(
+    state: &AppState,
+    auth_user: AuthUser,
+    input: NewReport,
+    target: ReportTarget,
+) -> AppJsonResult<Report> {
+    let reason = input.report.reason.trim().to_string();
+    if reason.is_empty() {
+        return Err(AppError::BadRequest);
+    }
+
+    let report = Mutation::report_content(
+        &state.client,
+        auth_user.user_id,
+        reason,
+        target,
+        state.config.accounts.report_threshold,
+    )
+    .await?;
+
+    let report = Query::get_report(&state.client, report.id).await?;
+    // Enough reports hide the article
+    if let Some(article) = &report.article {
+        cache::article_changed(state, &article.slug);
+    }
+
+    Ok(Json(Report {
+        report: report.into_report_body(),
+    }))
+}
+
+async fn handle_get_reports(
+    auth_user: AuthUser,
+    UrlQuery(params): UrlQuery<ReportParams>,
+    State(state): State<AppState>,
+) -> AppJsonResult<Reports> {
+    require_moderator(&auth_user)?;
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let status = params.status.map(ReportStatus::from);
+
+    let (reports, reports_count) = Query::get_reports(&state.client, status, limit, offset).await?;
+
+    Ok(Json(Reports {
+        reports: reports.into_iter().map(|x| x.into_report_body()).collect(),
+        reports_count,
+    }))
+}
+
+async fn handle_resolve_report(
+    auth_user: AuthUser,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<ReportResolution>,
+) -> Result<StatusCode, AppError> {
+    require_moderator(&auth_user)?;
+
+    let report = Query::get_report(&state.client, id).await?;
+    let target = match (&report.article_id, &report.comment_id) {
+        (Some(id), _) => ReportTarget::Article(id.clone()),
+        (_, Some(id)) => ReportTarget::Comment(id.clone()),
+        _ => return Err(AppError::NotFound),
+    };
+
+    let input = input.resolution;
+    let status = match input.action {
+        ReportAction::Dismiss => ReportStatus::Dismissed,
+        ReportAction::Hide => ReportStatus::Resolved,
+        ReportAction::Suspend => {
+            // Bans are for admins only
+            if input.expires_at.is_none() && auth_user.role != Role::Admin {
+                return Err(AppError::Forbidden);
+            }
+            if matches!(input.expires_at, Some(x) if x < Utc::now()) {
+                return Err(AppError::BadRequest);
+            }
+
+            let author_id = report.author().ok_or(AppError::NotFound)?.0.to_string();
+            let author = Query::get_user_by_id(&state.client, author_id).await?;
+            if matches!(author.role, DbRole::Admin | DbRole::Moderator) {
+                return Err(AppError::Forbidden);
+            }
+
+            Mutation::suspend_user(
+                &state.client,
+                auth_user.user_id.clone(),
+                author.id,
+                input.reason.unwrap_or_else(|| report.reason.clone()),
+                input.expires_at,
+            )
+            .await?;
+            cache::everything_changed(&state);
+
+            ReportStatus::Resolved
+        }
+    };
+
+    Mutation::resolve_reports(&state.client, auth_user.user_id, target, status).await?;
+    // Dismissing the reports shows a hidden article again
+    if let Some(article) = &report.article {
+        cache::article_changed(&state, &article.slug);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
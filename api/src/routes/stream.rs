@@ -0,0 +1,116 @@
+use axum::{
+    extract::{Json, Query as UrlQuery, State},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use db::query::Query;
+
+use crate::{
+    events::{Event, Subscriber},
+    error::AppError,
+    extractor::AuthUser,
+    AppJsonResult, AppResult, AppState,
+};
+
+use types::{
+    comment::CommentBody,
+    token::{Scope, StreamTicket, StreamTicketBody},
+};
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/stream", get(handle_stream))
+        .route("/api/stream/ticket", post(handle_stream_ticket))
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamParams {
+    /// Comma separated slugs of the articles the client is showing
+    articles: Option<String>,
+    /// From `/api/stream/ticket`, for clients that can't set the `Authorization` header
+    ticket: Option<String>,
+}
+
+// Comments say which of the watched articles they belong to
+#[derive(Serialize)]
+struct CommentEvent {
+    article: String,
+    comment: CommentBody,
+}
+
+// The browser's `EventSource` can't send headers, so it gets a ticket with
+// a regular request first and opens the stream with that
+async fn handle_stream_ticket(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> AppJsonResult<StreamTicket> {
+    auth_user.require_scope(Scope::Read)?;
+
+    let (token, expires_at) = auth_user.stream_ticket(&state);
+
+    Ok(Json(StreamTicket {
+        ticket: StreamTicketBody {
+            token,
+            expires_at: expires_at.into(),
+        },
+    }))
+}
+
+async fn handle_stream(
+    auth_user: Option<AuthUser>,
+    UrlQuery(params): UrlQuery<StreamParams>,
+    State(state): State<AppState>,
+) -> AppResult<Sse<impl Stream<Item = Result<SseEvent, axum::Error>>>> {
+    let auth_user = match (auth_user, &params.ticket) {
+        (Some(auth_user), _) => auth_user,
+        (None, Some(ticket)) => AuthUser::from_stream_ticket(&state, ticket).await?,
+        (None, None) => return Err(AppError::Unathorized),
+    };
+    auth_user.require_scope(Scope::Read)?;
+    let user_id = auth_user.user_id;
+
+    // Subscribe first, so nothing published while the rest loads is lost
+    let receiver = state.events.subscribe();
+
+    let follows = Query::get_user_follows_by_id(&state.client, user_id.clone()).await?;
+    let blocked = Query::get_blocked_users(&state.client, user_id.clone()).await?;
+    let muted = Query::get_muted_users(&state.client, user_id.clone()).await?;
+
+    let subscriber = Subscriber {
+        user_id,
+        articles: params
+            .articles
+            .unwrap_or_default()
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(str::to_string)
+            .collect(),
+        follows: follows.into_iter().collect(),
+        ignored: blocked.into_iter().chain(muted).map(|x| x.id).collect(),
+    };
+
+    // A stream that lagged behind just skips the events it missed
+    let events = BroadcastStream::new(receiver)
+        .filter_map(move |x| x.ok().and_then(|x| subscriber.filter(x)))
+        .map(into_sse_event);
+
+    Ok(Sse::new(state.shutdown.guard(events)).keep_alive(KeepAlive::default()))
+}
+
+fn into_sse_event(event: Event) -> Result<SseEvent, axum::Error> {
+    match event {
+        Event::Comment { slug, comment, .. } => SseEvent::default()
+            .event("comment")
+            .json_data(CommentEvent { article: slug, comment }),
+        Event::Notification { notification, .. } => SseEvent::default()
+            .event("notification")
+            .json_data(notification),
+        Event::Article { article, .. } => SseEvent::default()
+            .event("article")
+            .json_data(article),
+    }
+}
@@ -0,0 +1,80 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    routing::{delete, get},
+    Router,
+};
+
+use db::{mutation::Mutation, query::Query};
+
+use crate::{
+    error::AppError,
+    extractor::{AuthUser, ACCESS_TOKEN_PREFIX},
+    hashing::{generate_token, hash_token},
+    AppJsonResult, AppState,
+};
+
+use types::token::{AccessToken, AccessTokenBody, AccessTokens, NewAccessToken};
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/user/tokens",
+            get(handle_list_tokens).post(handle_create_token),
+        )
+        .route("/api/user/tokens/:id", delete(handle_revoke_token))
+}
+
+async fn handle_list_tokens(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> AppJsonResult<AccessTokens> {
+    auth_user.require_session()?;
+
+    let tokens: Vec<AccessTokenBody> = Query::get_access_tokens(&state.client, auth_user.user_id)
+        .await?
+        .into_iter()
+        .map(|x| x.into_access_token_body(None))
+        .collect();
+
+    Ok(Json(AccessTokens { tokens }))
+}
+
+async fn handle_create_token(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+    Json(input): Json<NewAccessToken>,
+) -> AppJsonResult<AccessToken> {
+    auth_user.require_session()?;
+
+    if input.token.name.trim().is_empty() || input.token.scopes.is_empty() {
+        return Err(AppError::BadRequest);
+    }
+
+    // The prefix tells the extractor this isn't a JWT
+    let secret = format!("{ACCESS_TOKEN_PREFIX}{}", generate_token().0);
+
+    let token = Mutation::create_access_token(
+        &state.client,
+        auth_user.user_id,
+        input,
+        hash_token(&secret),
+    )
+    .await?;
+
+    Ok(Json(AccessToken {
+        token: token.into_access_token_body(Some(secret)),
+    }))
+}
+
+async fn handle_revoke_token(
+    auth_user: AuthUser,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    auth_user.require_session()?;
+
+    Mutation::revoke_access_token(&state.client, id, auth_user.user_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
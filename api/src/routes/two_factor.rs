@@ -0,0 +1,174 @@
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    routing::post,
+    Router,
+};
+use chrono::Utc;
+
+use db::{mutation::Mutation, prisma::user, query::Query};
+
+use crate::{
+    error::AppError,
+    extractor::{AuthUser, TwoFactorChallenge},
+    hashing::{hash_password, verify_password},
+    routes::user::ensure_can_sign_in,
+    totp, AppJsonResult, AppResult, AppState,
+};
+
+use types::user::*;
+
+const RECOVERY_CODES: usize = 10;
+// Codes that can be tried with one challenge token before the user has to sign in again
+const CHALLENGE_ATTEMPTS: i32 = 5;
+
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/user/2fa",
+            post(handle_enroll_two_factor).delete(handle_disable_two_factor),
+        )
+        .route("/api/user/2fa/confirm", post(handle_confirm_two_factor))
+        .route("/api/users/login/2fa", post(handle_login_two_factor))
+}
+
+async fn handle_enroll_two_factor(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> AppJsonResult<TwoFactorEnrollment> {
+    auth_user.require_session()?;
+
+    let user = Query::get_user_by_id(&state.client, auth_user.user_id).await?;
+
+    if user.totp_enabled_at.is_some() {
+        return Err(AppError::Conflict);
+    }
+
+    let secret = totp::generate_secret();
+    Mutation::start_totp_enrollment(&state.client, user.id, secret.clone()).await?;
+
+    Ok(Json(TwoFactorEnrollment {
+        two_factor: TwoFactorEnrollmentBody {
+            otpauth_uri: totp::otpauth_uri(&secret, &user.username),
+            secret,
+        },
+    }))
+}
+
+async fn handle_confirm_two_factor(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> AppJsonResult<RecoveryCodes> {
+    auth_user.require_session()?;
+
+    let user = Query::get_user_by_id(&state.client, auth_user.user_id).await?;
+
+    if user.totp_enabled_at.is_some() {
+        return Err(AppError::Conflict);
+    }
+
+    // Only a TOTP code proves the authenticator app was set up correctly
+    let secret = user.totp_secret.as_deref().ok_or(AppError::NotFound)?;
+    let step = totp::verify(secret, &input.user.code, Utc::now().timestamp())
+        .ok_or(AppError::Unathorized)?;
+
+    if !Mutation::use_totp_step(&state.client, user.id.clone(), step as i32).await? {
+        return Err(AppError::Unathorized);
+    }
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| totp::generate_recovery_code())
+        .collect();
+
+    let mut hashes = Vec::with_capacity(RECOVERY_CODES);
+    for code in &recovery_codes {
+        hashes.push(hash_password(code.clone()).await?);
+    }
+
+    Mutation::enable_totp(&state.client, user.id, hashes).await?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+async fn handle_disable_two_factor(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> Result<StatusCode, AppError> {
+    auth_user.require_session()?;
+
+    let user = Query::get_user_by_id(&state.client, auth_user.user_id).await?;
+
+    if user.totp_enabled_at.is_some() {
+        check_second_factor(&state, &user, &input.user.code).await?;
+    }
+
+    Mutation::disable_totp(&state.client, user.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn handle_login_two_factor(
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorLogin>,
+) -> AppJsonResult<User> {
+    let challenge = TwoFactorChallenge::from_jwt(&state, &input.user.challenge_token)?;
+
+    // Every code tried, right or wrong, uses up an attempt. That bounds both
+    // the guessing and the recovery code hashing one challenge can cause.
+    let attempt = Mutation::use_login_challenge_attempt(
+        &state.client,
+        challenge.challenge_id.clone(),
+        challenge.user_id.clone(),
+        CHALLENGE_ATTEMPTS,
+    )
+    .await?;
+    if !attempt {
+        return Err(AppError::Unathorized);
+    }
+
+    let user = Query::get_user_by_id(&state.client, challenge.user_id).await?;
+
+    check_second_factor(&state, &user, &input.user.code).await?;
+    ensure_can_sign_in(&user)?;
+
+    Mutation::finish_login_challenge(&state.client, challenge.challenge_id, user.id.clone()).await?;
+
+    let token = AuthUser::for_user(&user).to_jwt(&state);
+
+    Ok(Json(user.into_user(token)))
+}
+
+// Accepts either a current TOTP code or one of the user's unused recovery codes
+async fn check_second_factor(state: &AppState, user: &user::Data, code: &str) -> AppResult<()> {
+    let secret = match (&user.totp_secret, &user.totp_enabled_at) {
+        (Some(secret), Some(_)) => secret,
+        _ => return Err(AppError::Unathorized),
+    };
+
+    if let Some(step) = totp::verify(secret, code, Utc::now().timestamp()) {
+        return match Mutation::use_totp_step(&state.client, user.id.clone(), step as i32).await? {
+            true => Ok(()),
+            false => Err(AppError::Unathorized),
+        };
+    }
+
+    let code = totp::normalize_recovery_code(code);
+    let recovery_codes = Query::get_unused_recovery_codes(&state.client, user.id.clone()).await?;
+
+    for recovery_code in recovery_codes {
+        match verify_password(code.clone(), recovery_code.code_hash).await {
+            Ok(()) => {
+                return match Mutation::use_recovery_code(&state.client, recovery_code.id).await? {
+                    true => Ok(()),
+                    false => Err(AppError::Unathorized),
+                };
+            }
+            Err(AppError::Unathorized) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(AppError::Unathorized)
+}
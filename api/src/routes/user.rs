@@ -1,23 +1,43 @@
 use axum::{
     extract::{Json, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
+use chrono::Utc;
+use tracing::error;
 
-use db::{mutation::Mutation, query::Query};
+use db::{
+    mutation::Mutation,
+    prisma::user::Data as UserData,
+    query::{is_suspended, Query},
+    DbErr,
+};
 
 use crate::{
-    extractor::AuthUser,
-    hashing::{hash_password, verify_password},
-    AppJsonResult, AppState,
+    cache,
+    error::AppError,
+    etag::{if_match_version, stale_as_precondition, tagged},
+    extractor::{AuthUser, TwoFactorChallenge},
+    hashing::{generate_token, hash_password, hash_token, verify_password},
+    mail::{lifetime, Mail},
+    AppJsonResult, AppResult, AppState,
 };
 
-use types::user::*;
+use types::{patch::Patch, user::*};
 
 pub fn create_routes() -> Router<AppState> {
     Router::new()
         .route("/api/users", post(handle_create_user))
         .route("/api/users/login", post(handle_login_user))
+        .route("/api/users/password-reset", post(handle_request_password_reset))
+        .route(
+            "/api/users/password-reset/confirm",
+            post(handle_confirm_password_reset),
+        )
+        .route("/api/users/verify", post(handle_verify_email))
+        .route("/api/users/verify/resend", post(handle_resend_verification))
         .route(
             "/api/user",
             get(handle_get_current_user).put(handle_update_user),
@@ -29,52 +49,232 @@ async fn handle_create_user(
     Json(mut input): Json<NewUserRequest>,
 ) -> AppJsonResult<User> {
     input.user.password = hash_password(input.user.password).await?;
-    let user = Mutation::create_user(&state.client, input).await?;
+    let user = Mutation::create_user(&state.client, input, state.config.username_cooldown()).await?;
+
+    // The account exists by now, a failed mail shouldn't turn that into an
+    // error. The user can ask for another one.
+    if send_verification_mail(&state, user.id.clone(), user.email.clone()).await.is_err() {
+        error!("Couldn't send the verification mail to user {}", user.id);
+    }
 
-    let user_id = user.id.clone();
+    let token = AuthUser::for_user(&user).to_jwt(&state);
 
-    Ok(Json(user.into_user(AuthUser { user_id  }.to_jwt(&state))))
+    Ok(Json(user.into_user(token)))
 }
 
 async fn handle_login_user(
     State(state): State<AppState>,
     Json(input): Json<LoginUser>,
-) -> AppJsonResult<User> {
+) -> AppResult<Response> {
     let user = Query::get_user_by_email(&state.client, input.user.email).await?;
 
     verify_password(input.user.password, user.password.clone()).await?;
 
-    let user_id = user.id.clone();
-
-    Ok(Json(user.into_user(AuthUser { user_id  }.to_jwt(&state))))
+    session_or_challenge(&state, user).await
 }
 
 async fn handle_get_current_user(
     auth_user: AuthUser,
     State(state): State<AppState>,
-) -> AppJsonResult<User> {
+) -> AppResult<Response> {
+    // This hands out a fresh session token, so it's off limits for access tokens
+    auth_user.require_session()?;
+
     let user = Query::get_user_by_id(&state.client, auth_user.user_id).await?;
 
-    let user_id = user.id.clone();
+    let token = AuthUser::for_user(&user).to_jwt(&state);
 
-    Ok(Json(user.into_user(AuthUser { user_id  }.to_jwt(&state))))
+    Ok(tagged(user.into_user(token)))
 }
 
 async fn handle_update_user(
     auth_user: AuthUser,
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(mut input): Json<UpdateUser>,
-) -> AppJsonResult<User> {
+) -> AppResult<Response> {
+    auth_user.require_session()?;
+
     if input == UpdateUser::default() {
         return handle_get_current_user(auth_user, State(state)).await;
     }
+    if input.user.clears_required() {
+        return Err(AppError::Unprocessable);
+    }
+
+    let if_match = if_match_version(&headers)?;
+    input.user.version = if_match.or(input.user.version);
+
+    if let Patch::Value(pssw) = input.user.password {
+        input.user.password = Patch::Value(hash_password(pssw).await?)
+    }
 
-    if let Some(pssw) = input.user.password {
-        input.user.password = Some(hash_password(pssw).await?)
+    // Sending the current email back shouldn't make the user verify it again
+    if let Patch::Value(email) = &input.user.email {
+        let current = Query::get_user_by_id(&state.client, auth_user.user_id.clone()).await?;
+        if *email == current.email {
+            input.user.email = Patch::Absent;
+        }
     }
+    let email_changed = !input.user.email.is_absent();
+
+    let user =
+        Mutation::update_user(&state.client, auth_user.user_id, input, state.config.username_cooldown())
+            .await
+            .map_err(stale_as_precondition(if_match))?;
+    // The author's name, bio and image show up on every cached article
+    cache::everything_changed(&state);
+
+    if email_changed && send_verification_mail(&state, user.id.clone(), user.email.clone()).await.is_err() {
+        error!("Couldn't send the verification mail to user {}", user.id);
+    }
+
+    let token = AuthUser::for_user(&user).to_jwt(&state);
+
+    Ok(tagged(user.into_user(token)))
+}
+
+async fn handle_request_password_reset(
+    State(state): State<AppState>,
+    Json(input): Json<PasswordResetRequest>,
+) -> StatusCode {
+    // Answer the same way, and just as fast, whether the email exists or not,
+    // so this can't be used to find out who has an account. The lookup and
+    // the mail happen in the background.
+    let shutdown = state.shutdown.clone();
+    shutdown.spawn(async move {
+        if send_password_reset(&state, input.user.email).await.is_err() {
+            error!("Couldn't send a password reset mail");
+        }
+    });
+
+    StatusCode::ACCEPTED
+}
+
+async fn send_password_reset(state: &AppState, email: String) -> AppResult<()> {
+    let user = match Query::get_user_by_email(&state.client, email).await {
+        Ok(user) => user,
+        Err(DbErr::NotFound) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let (token, token_hash) = generate_token();
+    let length = state.config.password_reset_length();
+    let expires_at = Utc::now() + length;
+
+    Mutation::create_password_reset(&state.client, user.id, token_hash, expires_at.into()).await?;
+
+    state
+        .mailer
+        .send(Mail {
+            to: user.email,
+            subject: "Reset your password".into(),
+            body: format!(
+                "Someone asked to reset the password of your account {}.\n\n\
+                Use this token to choose a new one, it expires in {}:\n\n{token}\n\n\
+                If it wasn't you, you can ignore this mail.",
+                user.username,
+                lifetime(length)
+            ),
+        })
+        .await
+}
 
-    let user = Mutation::update_user(&state.client, auth_user.user_id, input).await?;
-    let user_id = user.id.clone();
+async fn handle_confirm_password_reset(
+    State(state): State<AppState>,
+    Json(input): Json<PasswordResetConfirm>,
+) -> AppResult<Response> {
+    let password = hash_password(input.user.password).await?;
+    let user =
+        Mutation::reset_password(&state.client, hash_token(&input.user.token), password).await?;
+
+    session_or_challenge(&state, user).await
+}
+
+async fn handle_verify_email(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> AppResult<Response> {
+    let user = Mutation::verify_email(&state.client, hash_token(&input.user.token)).await?;
+
+    session_or_challenge(&state, user).await
+}
+
+async fn handle_resend_verification(
+    auth_user: AuthUser,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    auth_user.require_session()?;
+
+    let user = Query::get_user_by_id(&state.client, auth_user.user_id).await?;
+
+    if user.email_verified_at.is_some() {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    send_verification_mail(&state, user.id, user.email).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn send_verification_mail(state: &AppState, user_id: String, email: String) -> AppResult<()> {
+    let (token, token_hash) = generate_token();
+    let length = state.config.email_verification_length();
+    let expires_at = Utc::now() + length;
+
+    Mutation::create_email_verification(
+        &state.client,
+        user_id,
+        email.clone(),
+        token_hash,
+        expires_at.into(),
+    )
+    .await?;
+
+    state
+        .mailer
+        .send(Mail {
+            to: email,
+            subject: "Verify your email".into(),
+            body: format!(
+                "Welcome! Use this token to verify your email, it expires in {}:\n\n{token}\n",
+                lifetime(length)
+            ),
+        })
+        .await
+}
+
+// With 2FA enabled, proving the password or owning the mailbox only gets a
+// challenge token, which is exchanged in `two_factor::handle_login_two_factor`.
+pub async fn session_or_challenge(state: &AppState, user: UserData) -> AppResult<Response> {
+    ensure_can_sign_in(&user)?;
+
+    if user.totp_enabled_at.is_some() {
+        let expires_at = Utc::now() + TwoFactorChallenge::length();
+        let challenge_id =
+            Mutation::create_login_challenge(&state.client, user.id.clone(), expires_at.into())
+                .await?;
+        let challenge_token = TwoFactorChallenge {
+            user_id: user.id.clone(),
+            challenge_id,
+        }
+        .to_jwt(state);
+        return Ok(Json(LoginChallenge {
+            two_factor: LoginChallengeBody { challenge_token },
+        })
+        .into_response());
+    }
+
+    let token = AuthUser::for_user(&user).to_jwt(state);
+
+    Ok(Json(user.into_user(token)).into_response())
+}
+
+// Suspended users, and users an admin asked to pick a new password, can't get a session
+pub fn ensure_can_sign_in(user: &UserData) -> AppResult<()> {
+    if is_suspended(user.suspended_at, user.suspended_until) || user.password_reset_required {
+        return Err(AppError::Forbidden);
+    }
 
-    Ok(Json(user.into_user(AuthUser { user_id  }.to_jwt(&state))))
+    Ok(())
 }
@@ -0,0 +1,116 @@
+use std::{future::Future, sync::Arc};
+
+use tokio::{sync::watch, task::JoinHandle};
+use tokio_stream::{wrappers::WatchStream, Stream, StreamExt};
+
+// Tells long running work, like event streams, that the server is going down,
+// and keeps count of background tasks so they can finish first
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+    tasks: Arc<watch::Sender<usize>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        let (tasks, _) = watch::channel(0);
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+            tasks: Arc::new(tasks),
+        }
+    }
+}
+
+// Counts its task as running until dropped, which also covers a panic
+struct Running(Arc<watch::Sender<usize>>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.send_modify(|x| *x -= 1);
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        // The receiver kept here means there's always someone to send to
+        let _ = self.sender.send(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    pub async fn wait(self) {
+        let mut receiver = self.receiver;
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // Like `tokio::spawn`, but `drain` waits for the task
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.send_modify(|x| *x += 1);
+        let running = Running(self.tasks.clone());
+
+        tokio::spawn(async move {
+            let _running = running;
+            task.await
+        })
+    }
+
+    // Resolves once every task from `spawn` has finished
+    pub async fn drain(&self) {
+        let mut receiver = self.tasks.subscribe();
+        while *receiver.borrow() > 0 {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // Ends the stream once shutdown starts, so its connection can close
+    pub fn guard<S: Stream>(&self, stream: S) -> impl Stream<Item = S::Item> {
+        let stop = WatchStream::new(self.receiver.clone())
+            .filter(|x| *x)
+            .map(|_| None);
+
+        stream
+            .map(Some)
+            .merge(stop)
+            .take_while(Option::is_some)
+            .filter_map(|x| x)
+    }
+}
+
+// Resolves on SIGINT, or SIGTERM where there is one
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -1,7 +1,40 @@
-pub fn check_if_following<T: AsRef<str>>(follows: &[T], user_id: &str) -> bool {
-    follows.iter().any(|x| x.as_ref() == user_id)
+use axum::http::{header::COOKIE, HeaderMap};
+use db::query::Query;
+
+use crate::{error::AppError, AppResult, AppState};
+
+pub async fn ensure_email_verified(state: &AppState, user_id: &str) -> AppResult<()> {
+    if !state.config.accounts.require_verified_email {
+        return Ok(());
+    }
+
+    let user = Query::get_user_by_id(&state.client, user_id.to_string()).await?;
+
+    match user.email_verified_at {
+        Some(_) => Ok(()),
+        None => Err(AppError::Forbidden),
+    }
+}
+
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(';'))
+        .filter_map(|x| x.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
 }
 
-pub fn check_if_favorited<T: AsRef<str>>(favorites: &[T], article_id: &str) -> bool {
-    favorites.iter().any(|x| x.as_ref() == article_id)
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
 }
@@ -1,37 +1,107 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use axum::{Router, Server};
+use axum::{
+    extract::Form,
+    http::{
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+        HeaderMap,
+    },
+    routing::{get, post},
+    Json, Router, Server,
+};
 
-use db::prisma::PrismaClient;
+use db::prisma::{identity, user, PrismaClient, Role};
 use fake::{Fake, Faker};
-use realworld::{app, AppState};
+use realworld::{
+    app,
+    cache::ResponseCache,
+    config::{Config, LogFormat},
+    shutdown::Shutdown,
+    events::LocalBackend,
+    totp,
+    mail::{lifetime, Mail, MemoryMailer},
+    oidc::{pkce_challenge, OidcProvider},
+    AppState,
+};
 use reqwest::StatusCode;
+use serde_json::json;
+use tokio_stream::StreamExt;
 use std::net::{SocketAddr, TcpListener};
-use types::{user::{NewUserRequest, User}, article::{NewArticle, Article}};
+use types::{
+    admin::{AdminUsers, Impersonation},
+    article::{Article, MultipleArticles, NewArticle, Tags},
+    notification::{NotificationKind, Notifications},
+    report::Report,
+    token::{AccessToken, StreamTicket},
+    user::{
+        LoginChallenge, NewUserRequest, Profile, ProfileSearch, Profiles, RecoveryCodes,
+        Suggestions, TwoFactorEnrollment, User,
+    },
+};
 
-async fn get_app() -> Router {
+async fn get_client() -> Arc<PrismaClient> {
     dotenvy::dotenv().ok();
     let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
-    let client = Arc::new(
+    Arc::new(
         PrismaClient::_builder()
             .with_url(url)
             .build()
             .await
             .unwrap(),
-    );
+    )
+}
+
+fn test_config() -> Config {
+    let mut config = Config::default();
+    config.secrets.hmac_key = std::env::var("HMAC_KEY").expect("HMAC_KEY must be set");
+    config.accounts.report_threshold = 2;
+
+    config
+}
+
+async fn get_app(
+    mailer: Arc<MemoryMailer>,
+    oidc: Option<Arc<OidcProvider>>,
+    cache: Option<Arc<ResponseCache>>,
+    config: Config,
+) -> Router {
+    let client = get_client().await;
+
     let state = AppState {
         client,
-        hmac_key: std::env::var("HMAC_KEY")
-            .expect("HMAC_KEY must be set")
-            .into(),
+        mailer,
+        oidc,
+        events: Arc::new(LocalBackend::default()),
+        cache,
+        config: config.into(),
+        shutdown: Shutdown::default(),
     };
 
     app(state.into())
 }
 
-#[tokio::test]
-async fn basics() {
-    let app = get_app().await;
+async fn spawn_app() -> (SocketAddr, Arc<MemoryMailer>) {
+    spawn_app_with(None).await
+}
+
+async fn spawn_app_with(oidc: Option<Arc<OidcProvider>>) -> (SocketAddr, Arc<MemoryMailer>) {
+    serve(oidc, None).await
+}
+
+async fn serve(
+    oidc: Option<Arc<OidcProvider>>,
+    cache: Option<Arc<ResponseCache>>,
+) -> (SocketAddr, Arc<MemoryMailer>) {
+    serve_with_config(oidc, cache, test_config()).await
+}
+
+async fn serve_with_config(
+    oidc: Option<Arc<OidcProvider>>,
+    cache: Option<Arc<ResponseCache>>,
+    config: Config,
+) -> (SocketAddr, Arc<MemoryMailer>) {
+    let mailer = Arc::new(MemoryMailer::default());
+    let app = get_app(mailer.clone(), oidc, cache, config).await;
     let listener = TcpListener::bind("0.0.0.0:0".parse::<SocketAddr>().unwrap()).unwrap();
     let addr = listener.local_addr().unwrap();
 
@@ -43,6 +113,37 @@ async fn basics() {
             .unwrap();
     });
 
+    (addr, mailer)
+}
+
+// Some mails are sent in the background, after the response
+async fn wait_for_mail(mailer: &MemoryMailer, to: &str, subject: &str) -> Mail {
+    for _ in 0..50 {
+        let mail = mailer
+            .sent()
+            .into_iter()
+            .find(|x| x.to == to && x.subject == subject);
+        if let Some(mail) = mail {
+            return mail;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("No mail \"{subject}\" was sent to {to}");
+}
+
+fn mail_token(mail: &Mail) -> String {
+    mail.body
+        .lines()
+        .find(|x| x.len() == 64)
+        .expect("Mail has no token")
+        .to_string()
+}
+
+#[tokio::test]
+async fn basics() {
+    let (addr, _) = spawn_app().await;
+
     let client = reqwest::Client::new();
     let user: NewUserRequest = Faker.fake();
     let res = client
@@ -57,7 +158,7 @@ async fn basics() {
     let user_res: User = res.json().await.expect("Failed to serialize to user type");
     assert_eq!(user_res.user.username, user.user.username);
     assert_eq!(user_res.user.bio, "");
-    assert_eq!(user_res.user.image, Some("".to_string()));
+    assert_eq!(user_res.user.image, None);
 
     let article: NewArticle = Faker.fake();
     let res = client
@@ -74,3 +175,2427 @@ async fn basics() {
 
     assert_eq!(res.article.author.profile.username, user_res.user.username);
 }
+
+#[tokio::test]
+async fn password_reset() {
+    let (addr, mailer) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let user: NewUserRequest = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/users", addr))
+        .json(&user)
+        .send()
+        .await
+        .expect("Create user request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+    let session: User = res.json().await.expect("Failed to serialize to user type");
+
+    let res = client
+        .post(format!("http://{}/api/users/password-reset", addr))
+        .json(&json!({ "user": { "email": user.user.email } }))
+        .send()
+        .await
+        .expect("Password reset request failed");
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+    // Unknown emails get the same answer
+    let res = client
+        .post(format!("http://{}/api/users/password-reset", addr))
+        .json(&json!({ "user": { "email": "nobody@example.invalid" } }))
+        .send()
+        .await
+        .expect("Password reset request failed");
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+    let mail = wait_for_mail(&mailer, &user.user.email, "Reset your password").await;
+    let token = mail_token(&mail);
+
+    // Sessions are revoked by the second, so the old one has to be older than that
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let confirm = json!({ "user": { "token": token, "password": "a new password" } });
+    let res = client
+        .post(format!("http://{}/api/users/password-reset/confirm", addr))
+        .json(&confirm)
+        .send()
+        .await
+        .expect("Password reset confirm request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // The session from before the reset is signed out
+    let res = client
+        .get(format!("http://{}/api/user", addr))
+        .header("Authorization", format!("Token {}", session.user.token))
+        .send()
+        .await
+        .expect("Get user request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // Tokens are single use
+    let res = client
+        .post(format!("http://{}/api/users/password-reset/confirm", addr))
+        .json(&confirm)
+        .send()
+        .await
+        .expect("Password reset confirm request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = client
+        .post(format!("http://{}/api/users/login", addr))
+        .json(&json!({ "user": { "email": user.user.email, "password": "a new password" } }))
+        .send()
+        .await
+        .expect("Login request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn email_verification() {
+    let (addr, mailer) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let user: NewUserRequest = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/users", addr))
+        .json(&user)
+        .send()
+        .await
+        .expect("Create user request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let user_res: User = res.json().await.expect("Failed to serialize to user type");
+    assert!(!user_res.user.email_verified);
+
+    let mail = mailer
+        .sent()
+        .into_iter()
+        .find(|x| x.to == user.user.email && x.subject == "Verify your email")
+        .expect("No verification mail was sent");
+
+    let res = client
+        .post(format!("http://{}/api/users/verify", addr))
+        .json(&json!({ "user": { "token": mail_token(&mail) } }))
+        .send()
+        .await
+        .expect("Verify request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let user_res: User = res.json().await.expect("Failed to serialize to user type");
+    assert!(user_res.user.email_verified);
+}
+
+#[tokio::test]
+async fn verified_email_required() {
+    let mut config = test_config();
+    config.accounts.require_verified_email = true;
+    let (addr, mailer) = serve_with_config(None, None, config).await;
+
+    let client = reqwest::Client::new();
+    let (user, user_res) = register(&client, addr).await;
+    let auth = format!("Token {}", user_res.user.token);
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Create article request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let mail = wait_for_mail(&mailer, &user.user.email, "Verify your email").await;
+    let res = client
+        .post(format!("http://{}/api/users/verify", addr))
+        .json(&json!({ "user": { "token": mail_token(&mail) } }))
+        .send()
+        .await
+        .expect("Verify request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Create article request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+    let slug = slug_of(res).await;
+
+    let comment = json!({ "comment": { "body": "verified" } });
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&comment)
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Create comment request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // A new email has to be verified again
+    let email = format!("new-{}", user.user.email);
+    let res = client
+        .put(format!("http://{}/api/user", addr))
+        .json(&json!({ "user": { "email": email } }))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Update user request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let user_res: User = res.json().await.expect("Failed to serialize to user type");
+    assert!(!user_res.user.email_verified);
+
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&comment)
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Create comment request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&Faker.fake::<NewArticle>())
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Create article request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let mail = wait_for_mail(&mailer, &email, "Verify your email").await;
+    let res = client
+        .post(format!("http://{}/api/users/verify", addr))
+        .json(&json!({ "user": { "token": mail_token(&mail) } }))
+        .send()
+        .await
+        .expect("Verify request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&comment)
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Create comment request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+// RFC 6238, appendix B, cut down to six digits
+#[test]
+fn totp_vectors() {
+    // "12345678901234567890" in base32
+    let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    let vectors = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+
+    for (timestamp, expected) in vectors {
+        assert_eq!(totp::code(secret, timestamp).as_deref(), Some(expected));
+        assert_eq!(totp::verify(secret, expected, timestamp), Some(timestamp / 30));
+    }
+
+    assert_eq!(totp::verify(secret, "287082", 59 + 90), None);
+}
+
+async fn login(client: &reqwest::Client, addr: SocketAddr, user: &NewUserRequest) -> reqwest::Response {
+    client
+        .post(format!("http://{}/api/users/login", addr))
+        .json(&json!({ "user": { "email": user.user.email, "password": user.user.password } }))
+        .send()
+        .await
+        .expect("Login request failed")
+}
+
+async fn login_two_factor(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    challenge_token: &str,
+    code: &str,
+) -> reqwest::Response {
+    client
+        .post(format!("http://{}/api/users/login/2fa", addr))
+        .json(&json!({ "user": { "challengeToken": challenge_token, "code": code } }))
+        .send()
+        .await
+        .expect("Two factor login request failed")
+}
+
+#[tokio::test]
+async fn two_factor() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (user, user_res) = register(&client, addr).await;
+    let auth = format!("Token {}", user_res.user.token);
+
+    let enrollment: TwoFactorEnrollment = client
+        .post(format!("http://{}/api/user/2fa", addr))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Enroll request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to enrollment type");
+    let secret = enrollment.two_factor.secret;
+
+    let res = client
+        .post(format!("http://{}/api/user/2fa/confirm", addr))
+        .json(&json!({ "user": { "code": "000000" } }))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Confirm request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let now = chrono::Utc::now().timestamp();
+    let res = client
+        .post(format!("http://{}/api/user/2fa/confirm", addr))
+        .json(&json!({ "user": { "code": totp::code(&secret, now).unwrap() } }))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Confirm request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+    let codes: RecoveryCodes = res.json().await.expect("Failed to serialize to recovery codes");
+    assert_eq!(codes.recovery_codes.len(), 10);
+
+    // The password alone only gets a challenge
+    let challenge: LoginChallenge = login(&client, addr, &user)
+        .await
+        .json()
+        .await
+        .expect("Failed to serialize to challenge type");
+    let challenge = challenge.two_factor.challenge_token;
+
+    // The step used to confirm can't be replayed, the next one works
+    let res = login_two_factor(&client, addr, &challenge, &totp::code(&secret, now).unwrap()).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res =
+        login_two_factor(&client, addr, &challenge, &totp::code(&secret, now + 30).unwrap()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // A challenge is good for one login
+    let res = login_two_factor(&client, addr, &challenge, &codes.recovery_codes[0]).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // Recovery codes work once, with or without the dash
+    let challenge: LoginChallenge = login(&client, addr, &user).await.json().await.unwrap();
+    let challenge = challenge.two_factor.challenge_token;
+    let code = codes.recovery_codes[0].replace('-', "").to_uppercase();
+    let res = login_two_factor(&client, addr, &challenge, &code).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let challenge: LoginChallenge = login(&client, addr, &user).await.json().await.unwrap();
+    let challenge = challenge.two_factor.challenge_token;
+    let res = login_two_factor(&client, addr, &challenge, &codes.recovery_codes[0]).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // After a few wrong codes the challenge is spent, even for a right one
+    for _ in 0..4 {
+        let res = login_two_factor(&client, addr, &challenge, "000000").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+    let res = login_two_factor(&client, addr, &challenge, &codes.recovery_codes[1]).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let challenge: LoginChallenge = login(&client, addr, &user).await.json().await.unwrap();
+    let challenge = challenge.two_factor.challenge_token;
+    let res = login_two_factor(&client, addr, &challenge, &codes.recovery_codes[1]).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn access_tokens() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let user: NewUserRequest = Faker.fake();
+    let user_res: User = client
+        .post(format!("http://{}/api/users", addr))
+        .json(&user)
+        .send()
+        .await
+        .expect("Create user request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to user type");
+
+    let res = client
+        .post(format!("http://{}/api/user/tokens", addr))
+        .json(&json!({ "token": { "name": "ci", "scopes": ["articles:write"] } }))
+        .header("Authorization", format!("Token {}", user_res.user.token))
+        .send()
+        .await
+        .expect("Create token request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let token: AccessToken = res.json().await.expect("Failed to serialize to token type");
+    let secret = token.token.secret.expect("Token secret wasn't returned");
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", secret))
+        .send()
+        .await
+        .expect("Create article request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Not in the token's scopes
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug_of(res).await))
+        .json(&json!({ "comment": { "body": "from ci" } }))
+        .header("Authorization", format!("Token {}", secret))
+        .send()
+        .await
+        .expect("Create comment request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = client
+        .delete(format!("http://{}/api/user/tokens/{}", addr, token.token.id))
+        .header("Authorization", format!("Token {}", user_res.user.token))
+        .send()
+        .await
+        .expect("Revoke token request failed");
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", secret))
+        .send()
+        .await
+        .expect("Create article request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+async fn register(client: &reqwest::Client, addr: SocketAddr) -> (NewUserRequest, User) {
+    let user: NewUserRequest = Faker.fake();
+    let user_res: User = client
+        .post(format!("http://{}/api/users", addr))
+        .json(&user)
+        .send()
+        .await
+        .expect("Create user request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to user type");
+
+    (user, user_res)
+}
+
+async fn set_role(username: &str, role: Role) {
+    get_client()
+        .await
+        .user()
+        .update(
+            user::username::equals(username.to_string()),
+            vec![user::role::set(role)],
+        )
+        .exec()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn moderation() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (moderator, moderator_res) = register(&client, addr).await;
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    let res = client
+        .delete(format!("http://{}/api/articles/{}", addr, slug))
+        .header("Authorization", format!("Token {}", moderator_res.user.token))
+        .send()
+        .await
+        .expect("Delete article request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    set_role(&moderator.user.username, Role::Moderator).await;
+
+    // Logging in again shows the new role
+    let moderator_res: User = client
+        .post(format!("http://{}/api/users/login", addr))
+        .json(&json!({ "user": { "email": moderator.user.email, "password": moderator.user.password } }))
+        .send()
+        .await
+        .expect("Login request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to user type");
+    assert_eq!(moderator_res.user.role, types::user::Role::Moderator);
+
+    let res = client
+        .get(format!("http://{}/api/admin/audit", addr))
+        .header("Authorization", format!("Token {}", moderator_res.user.token))
+        .send()
+        .await
+        .expect("Audit log request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = client
+        .delete(format!("http://{}/api/articles/{}", addr, slug))
+        .header("Authorization", format!("Token {}", moderator_res.user.token))
+        .send()
+        .await
+        .expect("Delete article request failed");
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn admin_users() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (admin, admin_res) = register(&client, addr).await;
+    let (_, target) = register(&client, addr).await;
+    set_role(&admin.user.username, Role::Admin).await;
+
+    let article: NewArticle = Faker.fake();
+    client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", target.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+
+    let res: AdminUsers = client
+        .get(format!("http://{}/api/admin/users?q={}", addr, target.user.username))
+        .header("Authorization", format!("Token {}", admin_res.user.token))
+        .send()
+        .await
+        .expect("List users request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to users type");
+    assert_eq!(res.users_count, 1);
+    assert_eq!(res.users[0].username, target.user.username);
+
+    let res = client
+        .put(format!("http://{}/api/admin/users/{}/suspension", addr, target.user.username))
+        .json(&json!({ "suspension": { "reason": "spam" } }))
+        .header("Authorization", format!("Token {}", admin_res.user.token))
+        .send()
+        .await
+        .expect("Suspend request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .get(format!("http://{}/api/user", addr))
+        .header("Authorization", format!("Token {}", target.user.token))
+        .send()
+        .await
+        .expect("Get user request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res: MultipleArticles = client
+        .get(format!("http://{}/api/articles?author={}", addr, target.user.username))
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert!(res.articles.is_empty());
+
+    let res = client
+        .delete(format!("http://{}/api/admin/users/{}/suspension", addr, target.user.username))
+        .header("Authorization", format!("Token {}", admin_res.user.token))
+        .send()
+        .await
+        .expect("Lift suspension request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let impersonation: Impersonation = client
+        .post(format!("http://{}/api/admin/users/{}/impersonate", addr, target.user.username))
+        .header("Authorization", format!("Token {}", admin_res.user.token))
+        .send()
+        .await
+        .expect("Impersonate request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to impersonation type");
+
+    let res = client
+        .get(format!("http://{}/api/articles/feed", addr))
+        .header("Authorization", format!("Token {}", impersonation.impersonation.token))
+        .send()
+        .await
+        .expect("Feed request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", impersonation.impersonation.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Reading the user's notifications mustn't mark them read
+    let res = client
+        .post(format!("http://{}/api/notifications/read", addr))
+        .header("Authorization", format!("Token {}", impersonation.impersonation.token))
+        .send()
+        .await
+        .expect("Mark all read request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn reports() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, first) = register(&client, addr).await;
+    let (_, second) = register(&client, addr).await;
+    let (moderator, moderator_res) = register(&client, addr).await;
+    set_role(&moderator.user.username, Role::Moderator).await;
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    let report = json!({ "report": { "reason": "spam" } });
+    let res = client
+        .post(format!("http://{}/api/articles/{}/report", addr, slug))
+        .json(&report)
+        .header("Authorization", format!("Token {}", first.user.token))
+        .send()
+        .await
+        .expect("Report request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+    let report_id = res
+        .json::<Report>()
+        .await
+        .expect("Failed to serialize to report type")
+        .report
+        .id;
+
+    // Read-only tokens can't report
+    let res = client
+        .post(format!("http://{}/api/user/tokens", addr))
+        .json(&json!({ "token": { "name": "reader", "scopes": ["read"] } }))
+        .header("Authorization", format!("Token {}", second.user.token))
+        .send()
+        .await
+        .expect("Create token request failed");
+    let reader: AccessToken = res.json().await.expect("Failed to serialize to token type");
+    let res = client
+        .post(format!("http://{}/api/articles/{}/report", addr, slug))
+        .json(&report)
+        .header("Authorization", format!("Token {}", reader.token.secret.unwrap()))
+        .send()
+        .await
+        .expect("Report request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // A comment is only reported under its own article
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&json!({ "comment": { "body": "first" } }))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create comment request failed");
+    let comment: serde_json::Value = res.json().await.expect("Failed to serialize comment");
+    let comment_id = comment["comment"]["id"].as_str().unwrap().to_string();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&Faker.fake::<NewArticle>())
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let other_slug = slug_of(res).await;
+    let res = client
+        .post(format!(
+            "http://{}/api/articles/{}/comments/{}/report",
+            addr, other_slug, comment_id
+        ))
+        .json(&report)
+        .header("Authorization", format!("Token {}", first.user.token))
+        .send()
+        .await
+        .expect("Report request failed");
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // Reporting twice doesn't count twice
+    let res = client
+        .post(format!("http://{}/api/articles/{}/report", addr, slug))
+        .json(&report)
+        .header("Authorization", format!("Token {}", first.user.token))
+        .send()
+        .await
+        .expect("Report request failed");
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    let res = client
+        .get(format!("http://{}/api/articles/{}", addr, slug))
+        .send()
+        .await
+        .expect("Get article request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    client
+        .post(format!("http://{}/api/articles/{}/report", addr, slug))
+        .json(&report)
+        .header("Authorization", format!("Token {}", second.user.token))
+        .send()
+        .await
+        .expect("Report request failed");
+
+    // The threshold is 2 in tests
+    let res = client
+        .get(format!("http://{}/api/articles/{}", addr, slug))
+        .send()
+        .await
+        .expect("Get article request failed");
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = client
+        .get(format!("http://{}/api/admin/reports?status=open", addr))
+        .header("Authorization", format!("Token {}", first.user.token))
+        .send()
+        .await
+        .expect("Reports request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = client
+        .post(format!("http://{}/api/admin/reports/{}/resolve", addr, report_id))
+        .json(&json!({ "resolution": { "action": "dismiss" } }))
+        .header("Authorization", format!("Token {}", moderator_res.user.token))
+        .send()
+        .await
+        .expect("Resolve report request failed");
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+    let res = client
+        .get(format!("http://{}/api/articles/{}", addr, slug))
+        .send()
+        .await
+        .expect("Get article request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn block_and_mute() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (blocked, blocked_res) = register(&client, addr).await;
+    let (muted, muted_res) = register(&client, addr).await;
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    let res = client
+        .post(format!("http://{}/api/profiles/{}/block", addr, blocked.user.username))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Block request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&json!({ "comment": { "body": "hi" } }))
+        .header("Authorization", format!("Token {}", blocked_res.user.token))
+        .send()
+        .await
+        .expect("Create comment request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, author.user.username))
+        .header("Authorization", format!("Token {}", blocked_res.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let article: NewArticle = Faker.fake();
+    client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", muted_res.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+
+    client
+        .post(format!("http://{}/api/profiles/{}/mute", addr, muted.user.username))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Mute request failed");
+
+    let articles_by_muted = |token: Option<String>| {
+        let req = client.get(format!("http://{}/api/articles?author={}", addr, muted.user.username));
+        match token {
+            Some(token) => req.header("Authorization", format!("Token {}", token)),
+            None => req,
+        }
+    };
+
+    let res: MultipleArticles = articles_by_muted(Some(author.user.token.clone()))
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert!(res.articles.is_empty());
+
+    let res: MultipleArticles = articles_by_muted(None)
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert_eq!(res.articles.len(), 1);
+}
+
+#[tokio::test]
+async fn followers() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, follower) = register(&client, addr).await;
+    let (_, followed) = register(&client, addr).await;
+
+    let res = client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, followed.user.username))
+        .header("Authorization", format!("Token {}", follower.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res: Profile = client
+        .get(format!("http://{}/api/profiles/{}", addr, followed.user.username))
+        .send()
+        .await
+        .expect("Get profile request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to profile type");
+    let stats = res.profile.stats.expect("Profile has no stats");
+    assert_eq!(stats.followers_count, 1);
+    assert_eq!(stats.following_count, 0);
+
+    let res: Profiles = client
+        .get(format!("http://{}/api/profiles/{}/followers", addr, followed.user.username))
+        .send()
+        .await
+        .expect("Followers request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to profiles type");
+    assert_eq!(res.profiles.len(), 1);
+    assert_eq!(res.profiles[0].username, follower.user.username);
+
+    let res: Profiles = client
+        .get(format!("http://{}/api/profiles/{}/following", addr, follower.user.username))
+        .header("Authorization", format!("Token {}", follower.user.token))
+        .send()
+        .await
+        .expect("Following request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to profiles type");
+    assert_eq!(res.profiles.len(), 1);
+    assert!(res.profiles[0].following);
+}
+
+#[tokio::test]
+async fn profile_search() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, viewer) = register(&client, addr).await;
+    let (_, base) = register(&client, addr).await;
+    let tag = base.user.username;
+
+    let mut popular = None;
+    for suffix in ["quiet", "popular"] {
+        let mut user: NewUserRequest = Faker.fake();
+        user.user.username = format!("{tag}_{suffix}");
+        let res: User = client
+            .post(format!("http://{}/api/users", addr))
+            .json(&user)
+            .send()
+            .await
+            .expect("Create user request failed")
+            .json()
+            .await
+            .expect("Failed to serialize to user type");
+        popular = Some(res);
+    }
+    let popular = popular.unwrap();
+
+    client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, popular.user.username))
+        .header("Authorization", format!("Token {}", viewer.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+
+    // The most followed match comes first
+    let res: ProfileSearch = client
+        .get(format!("http://{}/api/profiles?q={}", addr, tag))
+        .header("Authorization", format!("Token {}", viewer.user.token))
+        .send()
+        .await
+        .expect("Search request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to profile search type");
+    assert_eq!(res.profiles_count, 3);
+    assert_eq!(res.profiles[0].username, popular.user.username);
+    assert!(res.profiles[0].following);
+
+    let res: ProfileSearch = client
+        .get(format!("http://{}/api/profiles?q={}&limit=1&offset=2", addr, tag))
+        .send()
+        .await
+        .expect("Search request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to profile search type");
+    assert_eq!(res.profiles.len(), 1);
+    assert_eq!(res.profiles_count, 3);
+
+    let res: Suggestions = client
+        .get(format!("http://{}/api/profiles/autocomplete?q={}&limit=2", addr, tag))
+        .send()
+        .await
+        .expect("Autocomplete request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to suggestions type");
+    assert_eq!(res.suggestions.len(), 2);
+    assert_eq!(res.suggestions[0].username, popular.user.username);
+}
+
+#[tokio::test]
+async fn username_history() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, user) = register(&client, addr).await;
+    let (_, other) = register(&client, addr).await;
+    let old = user.user.username.clone();
+    let new = format!("{old}_new");
+
+    let article: NewArticle = Faker.fake();
+    client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", user.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+
+    let res = client
+        .put(format!("http://{}/api/user", addr))
+        .json(&json!({ "user": { "username": new } }))
+        .header("Authorization", format!("Token {}", user.user.token))
+        .send()
+        .await
+        .expect("Update user request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let no_redirects = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let res = no_redirects
+        .get(format!("http://{}/api/profiles/{}", addr, old))
+        .send()
+        .await
+        .expect("Get profile request failed");
+    assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(res.headers()["location"], format!("/api/profiles/{new}"));
+
+    let res: Profile = client
+        .get(format!("http://{}/api/profiles/{}", addr, old))
+        .send()
+        .await
+        .expect("Get profile request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to profile type");
+    assert_eq!(res.profile.username, new);
+
+    let res: MultipleArticles = client
+        .get(format!("http://{}/api/articles?author={}", addr, old))
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert_eq!(res.articles.len(), 1);
+
+    // Admin actions only take the current name
+    set_role(&other.user.username, Role::Admin).await;
+    let res = client
+        .get(format!("http://{}/api/admin/users/{}", addr, old))
+        .header("Authorization", format!("Token {}", other.user.token))
+        .send()
+        .await
+        .expect("Get user request failed");
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = client
+        .get(format!("http://{}/api/admin/users/{}", addr, new))
+        .header("Authorization", format!("Token {}", other.user.token))
+        .send()
+        .await
+        .expect("Get user request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Nobody else can take the old name during the cooldown, but its owner can
+    let res = client
+        .put(format!("http://{}/api/user", addr))
+        .json(&json!({ "user": { "username": old } }))
+        .header("Authorization", format!("Token {}", other.user.token))
+        .send()
+        .await
+        .expect("Update user request failed");
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    let res = client
+        .put(format!("http://{}/api/user", addr))
+        .json(&json!({ "user": { "username": old } }))
+        .header("Authorization", format!("Token {}", user.user.token))
+        .send()
+        .await
+        .expect("Update user request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn viewer_flags() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, reader) = register(&client, addr).await;
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, author.user.username))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+    client
+        .post(format!("http://{}/api/articles/{}/favorite", addr, slug))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Favorite request failed");
+
+    let list = |token: Option<&str>| {
+        let req = client.get(format!("http://{}/api/articles?author={}", addr, author.user.username));
+        match token {
+            Some(token) => req.header("Authorization", format!("Token {}", token)),
+            None => req,
+        }
+    };
+
+    let res: MultipleArticles = list(Some(&reader.user.token))
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert!(res.articles[0].favorited);
+    assert!(res.articles[0].author.profile.following);
+    assert_eq!(res.articles[0].favorites_count, 1);
+
+    // The flags belong to the viewer, not to whoever favorited the article
+    let res: MultipleArticles = list(Some(&author.user.token))
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert!(!res.articles[0].favorited);
+    assert!(!res.articles[0].author.profile.following);
+
+    let res: MultipleArticles = list(None)
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert!(!res.articles[0].favorited);
+    assert_eq!(res.articles[0].favorites_count, 1);
+}
+
+#[tokio::test]
+async fn concurrent_favorites() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, reader) = register(&client, addr).await;
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let created: Article = res.json().await.expect("Failed to parse article");
+    let slug = created.article.slug.clone();
+
+    let get_article = || {
+        let client = client.clone();
+        let slug = slug.clone();
+        async move {
+            client
+                .get(format!("http://{}/api/articles/{}", addr, slug))
+                .send()
+                .await
+                .expect("Get article request failed")
+                .json::<Article>()
+                .await
+                .expect("Failed to parse article")
+                .article
+        }
+    };
+
+    // The same favorite sent many times at once only counts once
+    for favorite in [true, false] {
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let client = client.clone();
+                let url = format!("http://{}/api/articles/{}/favorite", addr, slug);
+                let token = format!("Token {}", reader.user.token);
+                tokio::spawn(async move {
+                    let req = if favorite { client.post(url) } else { client.delete(url) };
+                    req.header("Authorization", token)
+                        .send()
+                        .await
+                        .expect("Favorite request failed")
+                        .status()
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), StatusCode::OK);
+        }
+
+        let article = get_article().await;
+        assert_eq!(article.favorites_count, i32::from(favorite));
+    }
+
+    // Counters aren't edits
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&json!({ "comment": { "body": "first" } }))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Create comment request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let article = get_article().await;
+    assert_eq!(article.comments_count, 1);
+    assert_eq!(article.updated_at, created.article.updated_at);
+}
+
+#[tokio::test]
+async fn delete_comment() {
+    // With the cache on, a stale commentsCount would show
+    let cache = Arc::new(ResponseCache::new(100, Duration::from_secs(60)));
+    let (addr, _) = serve(None, Some(cache)).await;
+
+    let client = reqwest::Client::new();
+    let (_, user) = register(&client, addr).await;
+    let auth = format!("Token {}", user.user.token);
+
+    let mut slugs = vec![];
+    for _ in 0..2 {
+        let res = client
+            .post(format!("http://{}/api/articles", addr))
+            .json(&Faker.fake::<NewArticle>())
+            .header("Authorization", &auth)
+            .send()
+            .await
+            .expect("Create article request failed");
+        slugs.push(slug_of(res).await);
+    }
+
+    let comment: serde_json::Value = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slugs[0]))
+        .json(&json!({ "comment": { "body": "first" } }))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Create comment request failed")
+        .json()
+        .await
+        .expect("Failed to parse comment");
+    let id = comment["comment"]["id"].as_str().unwrap().to_string();
+
+    let get_article = || async {
+        client
+            .get(format!("http://{}/api/articles/{}", addr, slugs[0]))
+            .send()
+            .await
+            .expect("Get article request failed")
+            .json::<Article>()
+            .await
+            .expect("Failed to parse article")
+            .article
+    };
+    assert_eq!(get_article().await.comments_count, 1);
+
+    // Through another article's slug it isn't found
+    let res = client
+        .delete(format!("http://{}/api/articles/{}/comments/{}", addr, slugs[1], id))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Delete comment request failed");
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = client
+        .delete(format!("http://{}/api/articles/{}/comments/{}", addr, slugs[0], id))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .expect("Delete comment request failed");
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+    assert_eq!(get_article().await.comments_count, 0);
+}
+
+async fn notifications(client: &reqwest::Client, addr: SocketAddr, token: &str) -> Notifications {
+    client
+        .get(format!("http://{}/api/notifications", addr))
+        .header("Authorization", format!("Token {}", token))
+        .send()
+        .await
+        .expect("Notifications request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to notifications type")
+}
+
+#[tokio::test]
+async fn notifications_flow() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, reader) = register(&client, addr).await;
+
+    client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, author.user.username))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+
+    let res = notifications(&client, addr, &author.user.token).await;
+    assert_eq!(res.unread_count, 1);
+    assert_eq!(res.notifications[0].kind, NotificationKind::Follow);
+    assert_eq!(res.notifications[0].actor, reader.user.username);
+
+    let res = client
+        .post(format!("http://{}/api/notifications/read", addr))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Mark all read request failed");
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(notifications(&client, addr, &author.user.token).await.unread_count, 0);
+
+    client
+        .put(format!("http://{}/api/notifications/settings", addr))
+        .json(&json!({ "settings": { "muted": ["favorite"] } }))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Settings request failed");
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    client
+        .post(format!("http://{}/api/articles/{}/favorite", addr, slug))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Favorite request failed");
+    client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&json!({ "comment": { "body": "nice" } }))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Create comment request failed");
+
+    let res = notifications(&client, addr, &author.user.token).await;
+    assert_eq!(res.unread_count, 1);
+    assert_eq!(res.notifications[0].kind, NotificationKind::Comment);
+    assert_eq!(res.notifications[0].article, Some(slug));
+}
+
+#[tokio::test]
+async fn mentions() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, friend) = register(&client, addr).await;
+    let (_, blocker) = register(&client, addr).await;
+
+    client
+        .post(format!("http://{}/api/profiles/{}/block", addr, author.user.username))
+        .header("Authorization", format!("Token {}", blocker.user.token))
+        .send()
+        .await
+        .expect("Block request failed");
+
+    let mut article: NewArticle = Faker.fake();
+    article.article.body = format!(
+        "Thanks @{} and @{}.",
+        friend.user.username, blocker.user.username
+    );
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let created: Article = res.json().await.expect("Failed to parse article");
+    let slug = created.article.slug;
+
+    // Users who block the author can't be mentioned by them
+    assert_eq!(created.article.mentions.len(), 1);
+    assert_eq!(created.article.mentions[0].username, friend.user.username);
+
+    let res = notifications(&client, addr, &friend.user.token).await;
+    assert_eq!(res.notifications[0].kind, NotificationKind::Mention);
+    assert_eq!(res.notifications[0].actor, author.user.username);
+    assert_eq!(notifications(&client, addr, &blocker.user.token).await.unread_count, 0);
+
+    let res = client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&json!({ "comment": { "body": format!("cc @{}", author.user.username) } }))
+        .header("Authorization", format!("Token {}", friend.user.token))
+        .send()
+        .await
+        .expect("Create comment request failed");
+    let comment: serde_json::Value = res.json().await.expect("Failed to parse comment");
+    assert_eq!(comment["comment"]["mentions"][0]["username"], author.user.username);
+
+    // The body keeps the old name, the mention follows the user
+    let renamed = format!("{}-renamed", friend.user.username);
+    client
+        .put(format!("http://{}/api/user", addr))
+        .json(&json!({ "user": { "username": renamed } }))
+        .header("Authorization", format!("Token {}", friend.user.token))
+        .send()
+        .await
+        .expect("Update user request failed");
+
+    let res = client
+        .get(format!("http://{}/api/articles/{}", addr, slug))
+        .send()
+        .await
+        .expect("Get article request failed");
+    let article: Article = res.json().await.expect("Failed to parse article");
+    assert_eq!(article.article.mentions[0].handle, friend.user.username);
+    assert_eq!(article.article.mentions[0].username, renamed);
+}
+
+#[tokio::test]
+async fn health() {
+    let (addr, _) = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .get(format!("http://{}/healthz", addr))
+        .send()
+        .await
+        .expect("Health request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .get(format!("http://{}/readyz", addr))
+        .send()
+        .await
+        .expect("Readiness request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+    let readiness: serde_json::Value = res.json().await.expect("Failed to parse readiness");
+    assert_eq!(readiness["database"], "ok");
+    assert_eq!(readiness["build"]["version"], env!("CARGO_PKG_VERSION"));
+}
+
+#[tokio::test]
+async fn shutdown_ends_streams() {
+    let shutdown = Shutdown::default();
+    let mut events = Box::pin(shutdown.guard(tokio_stream::pending::<i32>()));
+
+    shutdown.trigger();
+    let next = tokio::time::timeout(Duration::from_secs(1), events.next())
+        .await
+        .expect("Stream kept going after shutdown");
+    assert_eq!(next, None);
+}
+
+#[tokio::test]
+async fn shutdown_drains_tasks() {
+    let shutdown = Shutdown::default();
+    let (done, finished) = tokio::sync::oneshot::channel();
+    shutdown.spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let _ = done.send(());
+    });
+
+    tokio::time::timeout(Duration::from_secs(1), shutdown.drain())
+        .await
+        .expect("Drain didn't wait for the task");
+    assert!(finished.await.is_ok());
+
+    // Nothing left running, so this is done right away
+    tokio::time::timeout(Duration::from_millis(10), shutdown.drain())
+        .await
+        .expect("Drain waited without any tasks");
+}
+
+#[test]
+fn config_layers() {
+    let config = Config::from_toml(
+        r#"
+        [server]
+        address = "127.0.0.1:3000"
+
+        [cors]
+        allowed_origins = ["https://conduit.example"]
+
+        [log]
+        format = "json"
+
+        [secrets]
+        hmac_key = "secret"
+        "#,
+    )
+    .expect("Failed to parse config");
+
+    assert_eq!(config.server.address.port(), 3000);
+    assert_eq!(config.log.format, LogFormat::Json);
+    // Sections that aren't in the file keep their defaults
+    assert_eq!(config.tokens.session_days, 14);
+    assert_eq!(config.accounts.report_threshold, 5);
+    assert!(config.validate().is_ok());
+
+    assert!(Config::from_toml("[server]\nport = 3000").is_err());
+
+    let mut config = Config::default();
+    assert!(config.validate().is_err());
+    config.secrets.hmac_key = "secret".into();
+    config.tokens.session_days = 0;
+    assert!(config.validate().is_err());
+    config.tokens.session_days = 1;
+    config.cors.allowed_headers = vec!["not a header".into()];
+    assert!(config.validate().is_err());
+}
+
+// Only sets variables no other test reads, since they're shared by the whole process
+#[test]
+fn config_env() {
+    let mut config = Config::default();
+    std::env::set_var("IMPERSONATION_MINUTES", "15");
+    std::env::set_var("PASSWORD_RESET_MINUTES", "20");
+    std::env::set_var("EMAIL_VERIFICATION_HOURS", "48");
+    std::env::set_var("IDEMPOTENCY_HOURS", "12");
+    std::env::set_var("IDEMPOTENCY_ABANDONED_SECONDS", "30");
+    std::env::set_var("REQUIRE_VERIFIED_EMAIL", "true");
+    std::env::set_var("MAIL_FROM", "conduit@example.com");
+    std::env::set_var("OIDC_ISSUER", "https://id.example.com");
+    std::env::set_var("OIDC_CLIENT_ID", "conduit");
+    std::env::set_var("OIDC_REDIRECT_URI", "https://conduit.example/api/auth/oidc/callback");
+    std::env::set_var("OIDC_CLIENT_SECRET", "oidc-secret");
+    config.apply_env().expect("Failed to apply the environment");
+
+    assert_eq!(config.tokens.impersonation_minutes, 15);
+    assert_eq!(config.tokens.password_reset_minutes, 20);
+    assert_eq!(config.tokens.email_verification_hours, 48);
+    assert_eq!(config.tokens.idempotency_hours, 12);
+    assert_eq!(config.tokens.idempotency_abandoned_seconds, 30);
+    assert!(config.accounts.require_verified_email);
+    assert_eq!(config.mail.from, "conduit@example.com");
+    assert_eq!(config.oidc.issuer.as_deref(), Some("https://id.example.com"));
+    assert_eq!(config.secrets.oidc_client_secret.as_deref(), Some("oidc-secret"));
+    let provider = OidcProvider::from_config(&config).expect("OIDC should be enabled");
+    assert_eq!(provider.issuer, "https://id.example.com");
+
+    // An issuer without the rest of its settings is turned away
+    config.oidc.client_id = None;
+    config.secrets.hmac_key = "secret".into();
+    assert!(config.validate().is_err());
+
+    std::env::set_var("IMPERSONATION_MINUTES", "an hour");
+    assert!(Config::default().apply_env().is_err());
+    std::env::remove_var("IMPERSONATION_MINUTES");
+    std::env::set_var("REQUIRE_VERIFIED_EMAIL", "yes");
+    assert!(Config::default().apply_env().is_err());
+
+    for name in [
+        "IMPERSONATION_MINUTES",
+        "PASSWORD_RESET_MINUTES",
+        "EMAIL_VERIFICATION_HOURS",
+        "IDEMPOTENCY_HOURS",
+        "IDEMPOTENCY_ABANDONED_SECONDS",
+        "REQUIRE_VERIFIED_EMAIL",
+        "MAIL_FROM",
+        "OIDC_ISSUER",
+        "OIDC_CLIENT_ID",
+        "OIDC_REDIRECT_URI",
+        "OIDC_CLIENT_SECRET",
+    ] {
+        std::env::remove_var(name);
+    }
+}
+
+#[test]
+fn mail_lifetimes() {
+    assert_eq!(lifetime(chrono::Duration::minutes(60)), "an hour");
+    assert_eq!(lifetime(chrono::Duration::minutes(90)), "90 minutes");
+    assert_eq!(lifetime(chrono::Duration::hours(24)), "a day");
+    assert_eq!(lifetime(chrono::Duration::hours(36)), "36 hours");
+    assert_eq!(lifetime(chrono::Duration::days(2)), "2 days");
+}
+
+#[test]
+fn cache_eviction() {
+    let cache = ResponseCache::new(2, Duration::from_secs(60));
+    cache.insert("a".to_string(), 1);
+    cache.insert("b".to_string(), 2);
+
+    // Reading "a" makes "b" the least recently used
+    assert_eq!(cache.get::<i32>("a"), Some(1));
+    cache.insert("c".to_string(), 3);
+    assert_eq!(cache.get::<i32>("b"), None);
+    assert_eq!(cache.get::<i32>("c"), Some(3));
+
+    cache.invalidate_prefix("c");
+    assert_eq!(cache.get::<i32>("c"), None);
+
+    let stats = cache.stats();
+    assert_eq!((stats.entries, stats.hits, stats.misses), (1, 2, 2));
+
+    let cache = ResponseCache::new(2, Duration::ZERO);
+    cache.insert("a".to_string(), 1);
+    assert_eq!(cache.get::<i32>("a"), None);
+}
+
+#[tokio::test]
+async fn response_cache() {
+    let cache = Arc::new(ResponseCache::new(100, Duration::from_secs(60)));
+    let (addr, _) = serve(None, Some(cache.clone())).await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, reader) = register(&client, addr).await;
+
+    let get_article = |slug: String| {
+        let client = client.clone();
+        async move {
+            let res = client
+                .get(format!("http://{}/api/articles/{}", addr, slug))
+                .send()
+                .await
+                .expect("Get article request failed");
+            let article: Article = res.json().await.expect("Failed to parse article");
+            article.article
+        }
+    };
+
+    let get_tags = || {
+        let client = client.clone();
+        async move {
+            let res = client
+                .get(format!("http://{}/api/tags", addr))
+                .send()
+                .await
+                .expect("Get tags request failed");
+            let tags: Tags = res.json().await.expect("Failed to parse tags");
+            tags.tags
+        }
+    };
+
+    let tag = format!("cached{}", author.user.username);
+    assert!(!get_tags().await.contains(&tag));
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&json!({ "article": {
+            "title": format!("Cached {}", author.user.username),
+            "description": "d",
+            "body": "first",
+            "tagList": [tag],
+        } }))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    get_article(slug.clone()).await;
+    assert_eq!(get_article(slug.clone()).await.body, "first");
+    assert_eq!(cache.stats().hits, 1);
+
+    client
+        .put(format!("http://{}/api/articles/{}", addr, slug))
+        .json(&json!({ "article": { "body": "second" } }))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Update article request failed");
+    assert_eq!(get_article(slug.clone()).await.body, "second");
+
+    client
+        .post(format!("http://{}/api/articles/{}/favorite", addr, slug))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Favorite request failed");
+    assert_eq!(get_article(slug.clone()).await.favorites_count, 1);
+
+    // Signed in readers skip the cache, their flags differ
+    let res = client
+        .get(format!("http://{}/api/articles/{}", addr, slug))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Get article request failed");
+    let article: Article = res.json().await.expect("Failed to parse article");
+    assert!(article.article.favorited);
+
+    assert!(get_tags().await.contains(&tag));
+
+    client
+        .delete(format!("http://{}/api/articles/{}", addr, slug))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Delete article request failed");
+    let res = client
+        .get(format!("http://{}/api/articles/{}", addr, slug))
+        .send()
+        .await
+        .expect("Get article request failed");
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn etags() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, reader) = register(&client, addr).await;
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    let get = |path: String, token: Option<String>, etag: Option<String>| {
+        let client = client.clone();
+        async move {
+            let mut req = client.get(format!("http://{}{}", addr, path));
+            if let Some(token) = token {
+                req = req.header("Authorization", format!("Token {}", token));
+            }
+            if let Some(etag) = etag {
+                req = req.header("If-None-Match", etag);
+            }
+            req.send().await.expect("Get request failed")
+        }
+    };
+    let etag_of = |res: &reqwest::Response| {
+        res.headers()["etag"].to_str().unwrap().to_string()
+    };
+
+    let path = format!("/api/articles/{}", slug);
+    let res = get(path.clone(), None, None).await;
+    assert_eq!(res.headers()["vary"], "authorization");
+    // Articles get a strong tag that leads with their version, for `If-Match`
+    let etag = etag_of(&res);
+    assert!(etag.starts_with("\"1-"));
+
+    let res = get(path.clone(), None, Some(etag.clone())).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(etag_of(&res), etag);
+
+    // Favoriting changes the count for everyone and the flag for the reader
+    let res = get(path.clone(), Some(reader.user.token.clone()), None).await;
+    let reader_etag = etag_of(&res);
+    client
+        .post(format!("http://{}/api/articles/{}/favorite", addr, slug))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Favorite request failed");
+    let res = get(path.clone(), None, Some(etag)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = get(path.clone(), Some(reader.user.token.clone()), Some(reader_etag)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Following shows up on the profile, but only for the follower
+    let path = format!("/api/profiles/{}", author.user.username);
+    let etag = etag_of(&get(path.clone(), None, None).await);
+    let reader_etag = etag_of(&get(path.clone(), Some(reader.user.token.clone()), None).await);
+    assert_eq!(etag, reader_etag);
+
+    client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, author.user.username))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+    let res = get(path.clone(), Some(reader.user.token.clone()), Some(reader_etag)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_ne!(etag_of(&res), etag);
+
+    let path = format!("/api/articles?author={}", author.user.username);
+    let etag = etag_of(&get(path.clone(), None, None).await);
+    let res = get(path, None, Some(format!("\"other\", {}", etag))).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+    // Other tests add tags concurrently, so only the wildcard is stable here
+    let res = get("/api/tags".to_string(), None, Some("*".to_string())).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn optimistic_concurrency() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    assert_eq!(author.user.version, 1);
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let created: Article = res.json().await.expect("Failed to parse article");
+    assert_eq!(created.article.version, 1);
+    let slug = created.article.slug;
+
+    let etag_of = |res: &reqwest::Response| {
+        res.headers()["etag"].to_str().unwrap().to_string()
+    };
+    let res = client
+        .get(format!("http://{}/api/articles/{}", addr, slug))
+        .send()
+        .await
+        .expect("Get article request failed");
+    let first = etag_of(&res);
+
+    let update = |body: serde_json::Value, if_match: Option<&str>| {
+        let mut req = client
+            .put(format!("http://{}/api/articles/{}", addr, slug))
+            .json(&body)
+            .header("Authorization", format!("Token {}", author.user.token));
+        if let Some(if_match) = if_match {
+            req = req.header("If-Match", if_match);
+        }
+        req.send()
+    };
+
+    let res = update(json!({ "article": { "body": "from tab one" } }), Some(&first))
+        .await
+        .expect("Update article request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+    let second = etag_of(&res);
+    assert!(second.starts_with("\"2-"));
+    assert_eq!(res.headers()["vary"], "authorization");
+    let updated: Article = res.json().await.expect("Failed to parse article");
+    assert_eq!(updated.article.version, 2);
+
+    // The second tab still has the first version
+    let res = update(json!({ "article": { "body": "from tab two" } }), Some(&first))
+        .await
+        .expect("Update article request failed");
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+    // Only the tags handed out are taken
+    for if_match in ["\"2\"", "W/\"2-0\"", "2"] {
+        let res = update(json!({ "article": { "body": "from tab two" } }), Some(if_match))
+            .await
+            .expect("Update article request failed");
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    let res = update(json!({ "article": { "body": "from tab two", "version": 1 } }), None)
+        .await
+        .expect("Update article request failed");
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    // The PUT's tag is good for the next edit
+    let res = update(json!({ "article": { "body": "whatever" } }), Some(&second))
+        .await
+        .expect("Update article request failed");
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client
+        .get(format!("http://{}/api/user", addr))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Get user request failed");
+    let first = etag_of(&res);
+    assert!(first.starts_with("\"1-"));
+
+    let res = client
+        .put(format!("http://{}/api/user", addr))
+        .json(&json!({ "user": { "bio": "one" } }))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .header("If-Match", &first)
+        .send()
+        .await
+        .expect("Update user request failed");
+    assert!(etag_of(&res).starts_with("\"2-"));
+    let user: User = res.json().await.expect("Failed to parse user");
+    assert_eq!(user.user.version, 2);
+
+    let res = client
+        .put(format!("http://{}/api/user", addr))
+        .json(&json!({ "user": { "bio": "two" } }))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .header("If-Match", &first)
+        .send()
+        .await
+        .expect("Update user request failed");
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+async fn idempotency_keys() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, other) = register(&client, addr).await;
+
+    let article: NewArticle = Faker.fake();
+    let create = |token: String, article: NewArticle| {
+        client
+            .post(format!("http://{}/api/articles", addr))
+            .json(&article)
+            .header("Authorization", format!("Token {}", token))
+            .header("Idempotency-Key", "create-1")
+            .send()
+    };
+
+    let first = create(author.user.token.clone(), article.clone())
+        .await
+        .expect("Create article request failed");
+    assert_eq!(first.status(), StatusCode::OK);
+    assert!(first.headers().get("idempotent-replayed").is_none());
+    let slug = slug_of(first).await;
+
+    let retry = create(author.user.token.clone(), article.clone())
+        .await
+        .expect("Create article request failed");
+    assert_eq!(retry.status(), StatusCode::OK);
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert_eq!(slug_of(retry).await, slug);
+
+    let articles: MultipleArticles = client
+        .get(format!("http://{}/api/articles?author={}", addr, author.user.username))
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to parse articles");
+    assert_eq!(articles.articles_count, 1);
+
+    // The same key can't be used for something else
+    let mut changed = article.clone();
+    changed.article.body = format!("{} again", changed.article.body);
+    let res = create(author.user.token.clone(), changed)
+        .await
+        .expect("Create article request failed");
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Keys belong to a user
+    let mut theirs = article.clone();
+    theirs.article.title = format!("{} too", theirs.article.title);
+    let res = create(other.user.token.clone(), theirs)
+        .await
+        .expect("Create article request failed");
+    assert!(res.headers().get("idempotent-replayed").is_none());
+
+    for _ in 0..2 {
+        let res = client
+            .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+            .json(&json!({ "comment": { "body": "just once" } }))
+            .header("Authorization", format!("Token {}", other.user.token))
+            .header("Idempotency-Key", "comment-1")
+            .send()
+            .await
+            .expect("Create comment request failed");
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let comments: serde_json::Value = client
+        .get(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .send()
+        .await
+        .expect("Get comments request failed")
+        .json()
+        .await
+        .expect("Failed to parse comments");
+    assert_eq!(comments["comments"].as_array().unwrap().len(), 1);
+}
+
+// The client hangs up right after sending, the retry still finds the one article
+#[tokio::test]
+async fn idempotency_cancelled() {
+    use tokio::io::AsyncWriteExt;
+
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let article: NewArticle = Faker.fake();
+    let body = serde_json::to_string(&article).unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "POST /api/articles HTTP/1.1\r\nHost: {addr}\r\nAuthorization: Token {}\r\n\
+        Idempotency-Key: cancelled-1\r\nContent-Type: application/json\r\n\
+        Content-Length: {}\r\n\r\n{body}",
+        author.user.token,
+        body.len(),
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    drop(stream);
+
+    let mut status = StatusCode::CONFLICT;
+    for _ in 0..50 {
+        let res = client
+            .post(format!("http://{}/api/articles", addr))
+            .json(&article)
+            .header("Authorization", format!("Token {}", author.user.token))
+            .header("Idempotency-Key", "cancelled-1")
+            .send()
+            .await
+            .expect("Create article request failed");
+        status = res.status();
+        if status != StatusCode::CONFLICT {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(status, StatusCode::OK);
+
+    let res: MultipleArticles = client
+        .get(format!("http://{}/api/articles?author={}", addr, author.user.username))
+        .send()
+        .await
+        .expect("List articles request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to articles type");
+    assert_eq!(res.articles.len(), 1);
+}
+
+#[tokio::test]
+async fn patch_fields() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, user) = register(&client, addr).await;
+
+    let update = |body: serde_json::Value| {
+        client
+            .put(format!("http://{}/api/user", addr))
+            .json(&body)
+            .header("Authorization", format!("Token {}", user.user.token))
+            .send()
+    };
+
+    let res = update(json!({ "user": { "image": "https://example.com/me.png", "bio": "hi" } }))
+        .await
+        .expect("Update user request failed");
+    let updated: User = res.json().await.expect("Failed to parse user");
+    assert_eq!(updated.user.image.as_deref(), Some("https://example.com/me.png"));
+
+    // Fields that aren't sent stay as they are
+    let res = update(json!({ "user": { "bio": "hello" } }))
+        .await
+        .expect("Update user request failed");
+    let updated: User = res.json().await.expect("Failed to parse user");
+    assert_eq!(updated.user.image.as_deref(), Some("https://example.com/me.png"));
+    assert_eq!(updated.user.bio, "hello");
+
+    let res = update(json!({ "user": { "image": null, "bio": null } }))
+        .await
+        .expect("Update user request failed");
+    let updated: User = res.json().await.expect("Failed to parse user");
+    assert_eq!(updated.user.image, None);
+    assert_eq!(updated.user.bio, "");
+
+    let res = client
+        .get(format!("http://{}/api/profiles/{}", addr, user.user.username))
+        .send()
+        .await
+        .expect("Get profile request failed");
+    let profile: Profile = res.json().await.expect("Failed to parse profile");
+    assert_eq!(profile.profile.image, None);
+
+    let res = update(json!({ "user": { "username": null } }))
+        .await
+        .expect("Update user request failed");
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", user.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    let res = client
+        .put(format!("http://{}/api/articles/{}", addr, slug))
+        .json(&json!({ "article": { "title": null } }))
+        .header("Authorization", format!("Token {}", user.user.token))
+        .send()
+        .await
+        .expect("Update article request failed");
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+// Reads the stream until an event of this kind comes, and returns its data
+async fn next_event(res: &mut reqwest::Response, kind: &str) -> serde_json::Value {
+    let mut buffer = String::new();
+    loop {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), res.chunk())
+            .await
+            .expect("Timed out waiting for an event")
+            .expect("Stream failed")
+            .expect("Stream ended");
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+        while let Some(end) = buffer.find("\n\n") {
+            let message: String = buffer.drain(..end + 2).collect();
+            let field = |name: &str| {
+                message
+                    .lines()
+                    .find_map(|x| x.strip_prefix(name))
+                    .map(|x| x.trim_start().to_string())
+            };
+            if field("event:").as_deref() == Some(kind) {
+                let data = field("data:").expect("Event has no data");
+                return serde_json::from_str(&data).expect("Event data isn't JSON");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn stream() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, reader) = register(&client, addr).await;
+
+    let article: NewArticle = Faker.fake();
+    let res = client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+    let slug = slug_of(res).await;
+
+    let mut events = client
+        .get(format!("http://{}/api/stream?articles={}", addr, slug))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Stream request failed");
+    assert_eq!(events.status(), StatusCode::OK);
+
+    client
+        .post(format!("http://{}/api/articles/{}/comments", addr, slug))
+        .json(&json!({ "comment": { "body": "first" } }))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Create comment request failed");
+
+    let event = next_event(&mut events, "comment").await;
+    assert_eq!(event["article"], slug);
+    assert_eq!(event["comment"]["body"], "first");
+
+    let event = next_event(&mut events, "notification").await;
+    assert_eq!(event["kind"], "comment");
+    assert_eq!(event["actor"], reader.user.username);
+
+    client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, author.user.username))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+
+    let event = next_event(&mut events, "notification").await;
+    assert_eq!(event["kind"], "follow");
+
+    // Followers get new articles from the author as they're published
+    let mut feed = client
+        .get(format!("http://{}/api/stream", addr))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Stream request failed");
+
+    let article: NewArticle = Faker.fake();
+    client
+        .post(format!("http://{}/api/articles", addr))
+        .json(&article)
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Create article request failed");
+
+    let event = next_event(&mut feed, "article").await;
+    assert_eq!(event["title"], article.article.title);
+    assert_eq!(event["author"]["following"], true);
+
+    let res = client
+        .get(format!("http://{}/api/stream", addr))
+        .send()
+        .await
+        .expect("Stream request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+// What a browser's EventSource, which can't set headers, does
+#[tokio::test]
+async fn stream_ticket() {
+    let (addr, _) = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, author) = register(&client, addr).await;
+    let (_, reader) = register(&client, addr).await;
+
+    let ticket: StreamTicket = client
+        .post(format!("http://{}/api/stream/ticket", addr))
+        .header("Authorization", format!("Token {}", author.user.token))
+        .send()
+        .await
+        .expect("Ticket request failed")
+        .json()
+        .await
+        .expect("Failed to serialize to ticket type");
+
+    let mut events = client
+        .get(format!("http://{}/api/stream?ticket={}", addr, ticket.ticket.token))
+        .send()
+        .await
+        .expect("Stream request failed");
+    assert_eq!(events.status(), StatusCode::OK);
+
+    client
+        .post(format!("http://{}/api/profiles/{}/follow", addr, author.user.username))
+        .header("Authorization", format!("Token {}", reader.user.token))
+        .send()
+        .await
+        .expect("Follow request failed");
+
+    let event = next_event(&mut events, "notification").await;
+    assert_eq!(event["kind"], "follow");
+
+    // Tickets only open streams, and sessions aren't tickets
+    let res = client
+        .get(format!("http://{}/api/user", addr))
+        .header("Authorization", format!("Token {}", ticket.ticket.token))
+        .send()
+        .await
+        .expect("Get user request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = client
+        .get(format!("http://{}/api/stream?ticket={}", addr, author.user.token))
+        .send()
+        .await
+        .expect("Stream request failed");
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+async fn slug_of(res: reqwest::Response) -> String {
+    let article: Article = res.json().await.expect("Failed to serialize to article type");
+    article.article.slug
+}
+
+// A minimal OpenID Connect provider. The test uses the PKCE challenge as the
+// authorization code, so the token endpoint can check the verifier without any state.
+async fn spawn_mock_issuer() -> SocketAddr {
+    let listener = TcpListener::bind("0.0.0.0:0".parse::<SocketAddr>().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let issuer = format!("http://{}", addr);
+
+    let app = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(move || async move {
+                Json(json!({
+                    "issuer": issuer,
+                    "authorization_endpoint": format!("{issuer}/authorize"),
+                    "token_endpoint": format!("{issuer}/token"),
+                    "userinfo_endpoint": format!("{issuer}/userinfo"),
+                }))
+            }),
+        )
+        .route(
+            "/token",
+            post(|Form(form): Form<HashMap<String, String>>| async move {
+                if pkce_challenge(&form["code_verifier"]) != form["code"] {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                Ok(Json(json!({ "access_token": form["code"], "token_type": "Bearer" })))
+            }),
+        )
+        .route(
+            "/userinfo",
+            get(|headers: HeaderMap| async move {
+                let token = headers[AUTHORIZATION].to_str().unwrap()["Bearer ".len()..].to_string();
+                Json(json!({
+                    "sub": token,
+                    "email": format!("{}@example.com", &token[..16]),
+                    "email_verified": true,
+                    "preferred_username": "oidc user",
+                }))
+            }),
+        );
+
+    tokio::spawn(async move {
+        Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    });
+
+    addr
+}
+
+// Returns the PKCE challenge, used as the authorization code, the state and
+// the cookie that binds the login to this client
+async fn oidc_authorize(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    token: Option<&str>,
+) -> (String, String, String) {
+    let mut req = client.get(format!("http://{}/api/auth/oidc/authorize", addr));
+    if let Some(token) = token {
+        req = req.header("Authorization", format!("Token {}", token));
+    }
+    let res = req.send().await.expect("Authorize request failed");
+
+    let cookie = res.headers()[SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    assert!(cookie.starts_with("oidc_state="));
+
+    let res: serde_json::Value = res.json().await.expect("Failed to serialize authorize response");
+    let url = reqwest::Url::parse(res["authorizationUrl"].as_str().unwrap()).unwrap();
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    assert_eq!(params["code_challenge_method"], "S256");
+
+    (params["code_challenge"].clone(), params["state"].clone(), cookie)
+}
+
+async fn oidc_callback(
+    client: &reqwest::Client,
+    addr: SocketAddr,
+    code: &str,
+    state: &str,
+    cookie: Option<&str>,
+) -> reqwest::Response {
+    let mut req = client
+        .post(format!("http://{}/api/auth/oidc/callback", addr))
+        .json(&json!({ "code": code, "state": state }));
+    if let Some(cookie) = cookie {
+        req = req.header(COOKIE, cookie);
+    }
+
+    req.send().await.expect("Callback request failed")
+}
+
+async fn spawn_oidc_app() -> SocketAddr {
+    let issuer = spawn_mock_issuer().await;
+    let provider = OidcProvider::new(
+        format!("http://{}", issuer),
+        "realworld".into(),
+        None,
+        "http://localhost:8080/oidc/callback".into(),
+    );
+    let (addr, _) = spawn_app_with(Some(Arc::new(provider))).await;
+
+    addr
+}
+
+#[tokio::test]
+async fn oidc_login() {
+    let addr = spawn_oidc_app().await;
+
+    let client = reqwest::Client::new();
+    let (code, state, cookie) = oidc_authorize(&client, addr, None).await;
+
+    // Finishing a login started in another browser doesn't work
+    let res = oidc_callback(&client, addr, &code, &state, None).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = oidc_callback(&client, addr, &code, &state, Some(&cookie)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let user_res: User = res.json().await.expect("Failed to serialize to user type");
+    assert!(user_res.user.email_verified);
+    assert!(user_res.user.username.starts_with("oidcuser"));
+
+    // The state can only be used once
+    let res = oidc_callback(&client, addr, &code, &state, Some(&cookie)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // A wrong verifier is rejected by the provider
+    let (_, state, cookie) = oidc_authorize(&client, addr, None).await;
+    let res = oidc_callback(&client, addr, &code, &state, Some(&cookie)).await;
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn oidc_linking() {
+    let addr = spawn_oidc_app().await;
+
+    let client = reqwest::Client::new();
+    let (_, attacker) = register(&client, addr).await;
+    let (_, victim) = register(&client, addr).await;
+
+    // Started while logged in, the provider account is linked to that user
+    let (code, state, cookie) = oidc_authorize(&client, addr, Some(&attacker.user.token)).await;
+    let res = oidc_callback(&client, addr, &code, &state, Some(&cookie)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let user_res: User = res.json().await.expect("Failed to serialize to user type");
+    assert_eq!(user_res.user.username, attacker.user.username);
+
+    // Someone else's browser can't finish the linking, not even with a login
+    // of its own under way
+    let (code, state, _) = oidc_authorize(&client, addr, Some(&attacker.user.token)).await;
+    let (_, _, victim_cookie) = oidc_authorize(&client, addr, Some(&victim.user.token)).await;
+    let res = oidc_callback(&client, addr, &code, &state, None).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = oidc_callback(&client, addr, &code, &state, Some(&victim_cookie)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let linked = get_client()
+        .await
+        .identity()
+        .count(vec![identity::user::is(vec![user::username::equals(
+            attacker.user.username.clone(),
+        )])])
+        .exec()
+        .await
+        .unwrap();
+    assert_eq!(linked, 1);
+}
@@ -9,7 +9,10 @@ pub mod mutation;
 pub enum DbErr {
     NotFound,
     QueryError(QueryError),
-    Unauthorized
+    Unauthorized,
+    Conflict,
+    // The record changed since the version the client sent
+    Stale
 }
 
 impl From<QueryError> for DbErr {
@@ -18,6 +21,10 @@ impl From<QueryError> for DbErr {
     }
 }
 
-pub async fn get_client() -> Result<PrismaClient, NewClientError> {
-    PrismaClient::_builder().build().await
+// Without a url the client uses the `DATABASE_URL` from the schema
+pub async fn get_client(url: Option<String>) -> Result<PrismaClient, NewClientError> {
+    match url {
+        Some(url) => PrismaClient::_builder().with_url(url).build().await,
+        None => PrismaClient::_builder().build().await,
+    }
 }
\ No newline at end of file
@@ -1,16 +1,29 @@
-use prisma_client_rust::QueryError;
+use prisma_client_rust::{
+    chrono::{DateTime, Duration, FixedOffset, Utc},
+    operator::or,
+    raw, PrismaValue, QueryError,
+};
+use tracing::error;
+use uuid::Uuid;
 use types::{
     article::{Article, ArticleBody, NewArticle, UpdateArticle},
     comment::{Comment, NewComment, CommentBody},
-    user::{NewUserRequest, Profile, UpdateUser, ProfileBody},
+    patch::Patch,
+    user::{Mention, NewUserRequest, Profile, UpdateUser, ProfileBody},
+    token::{AccessTokenBody, NewAccessToken},
 };
 
-use crate::DbErr;
+use crate::{
+    query::{avatar, notification_with_actor},
+    DbErr, UniqueKeyViolation,
+};
 
 use super::prisma::{
-    article, comment,
+    access_token, article, audit_log, comment, email_verification, idempotency_key, identity,
+    login_challenge, mention, oidc_login,
+    password_reset, notification, recovery_code, report, username_change,
     user::{self, SetParam},
-    PrismaClient,
+    NotificationKind, PrismaClient, ReportStatus, Role,
 };
 
 article::include!(article_with_user {
@@ -19,25 +32,30 @@ article::include!(article_with_user {
         username
         bio
         image
-        favorites: select {
-            id
-        }
-        follows: select {
-            id
-        }
     }
-    favorites: select {
-        id
+    mentions: include {
+        user: select {
+            username
+        }
     }
 });
 
 comment::include!(comment_with_author {
-    author: include {
-        favorites
-        follows
+    author: select {
+        id
+        username
+        bio
+        image
+    }
+    mentions: include {
+        user: select {
+            username
+        }
     }
 });
 
+user::select!(mentioned_user { id username });
+
 pub trait ArticleToJson {
     fn into_article_body(self, following: bool, favorited: bool) -> ArticleBody;
     fn into_article(self, following: bool, favorited: bool) -> Article;
@@ -60,15 +78,23 @@ impl ArticleToJson for ArticleData {
             created_at: self.created_at,
             updated_at: self.updated_at,
             favorited,
-            favorites_count: self.favorites.len() as i32,
+            favorites_count: self.favorites_count,
+            comments_count: self.comments_count,
+            version: self.version,
             author: Profile {
                 profile: ProfileBody {
                     following,
                     username: self.user.username,
                     bio: self.user.bio,
-                    image: Some(self.user.image),
+                    image: avatar(self.user.image),
+                    stats: None,
                 }
             },
+            mentions: self
+                .mentions
+                .into_iter()
+                .map(|x| Mention { handle: x.handle, username: x.user.username })
+                .collect(),
         }
     }
 }
@@ -85,15 +111,35 @@ impl comment_with_author::Data {
                     profile: ProfileBody { 
                         username: self.author.username,
                         bio: self.author.bio,
-                        image: Some(self.author.image),
+                        image: avatar(self.author.image),
                         following,
+                        stats: None,
                      },
                 },
+                mentions: self
+                    .mentions
+                    .into_iter()
+                    .map(|x| Mention { handle: x.handle, username: x.user.username })
+                    .collect(),
             },
         }
     }
 }
 
+impl access_token::Data {
+    pub fn into_access_token_body(self, secret: Option<String>) -> AccessTokenBody {
+        AccessTokenBody {
+            id: self.id,
+            name: self.name,
+            scopes: self.scopes.iter().filter_map(|x| x.parse().ok()).collect(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            last_used_at: self.last_used_at,
+            secret,
+        }
+    }
+}
+
 pub struct Mutation;
 
 impl Mutation {
@@ -101,29 +147,61 @@ impl Mutation {
         db: &PrismaClient,
         input: NewArticle,
         author: String,
-    ) -> Result<ArticleData, DbErr> {
-        let article = db
-            .article()
-            .create(
-                slug::slugify(&input.article.title),
-                input.article.title,
-                input.article.description,
-                input.article.body,
-                user::id::equals(author),
-                vec![article::tag_list::set(input.article.tag_list)],
-            )
-            .include(article_with_user::include())
+    ) -> Result<(ArticleData, Vec<notification_with_actor::Data>), DbErr> {
+        let mentioned = db
+            .user()
+            .find_many(mentioned_users(&author, &input.article.body))
+            .select(mentioned_user::select())
             .exec()
+            .await?;
+
+        // The id is picked here so the mentions can go in the same batch
+        let id = Uuid::new_v4().to_string();
+        let mentions = mentioned
+            .iter()
+            .map(|x| {
+                mention::create_unchecked(
+                    x.username.clone(),
+                    x.id.clone(),
+                    vec![mention::article_id::set(Some(id.clone()))],
+                )
+            })
+            .collect();
+
+        let (_, _, article) = db
+            ._batch((
+                db.article().create(
+                    slug::slugify(&input.article.title),
+                    input.article.title,
+                    input.article.description,
+                    input.article.body,
+                    user::id::equals(author.clone()),
+                    vec![
+                        article::id::set(id.clone()),
+                        article::tag_list::set(input.article.tag_list),
+                    ],
+                ),
+                db.mention().create_many(mentions),
+                db.article()
+                    .find_unique(article::id::equals(id.clone()))
+                    .include(article_with_user::include()),
+            ))
             .await
             .map_err(DbErr::QueryError)?;
+        let article = article.ok_or(DbErr::NotFound)?;
+
+        let notifications = Mutation::notify_mentioned(db, author, id, None, mentioned).await;
 
-        Ok(article)
+        Ok((article, notifications))
     }
 
     pub async fn create_user(
         db: &PrismaClient,
         input: NewUserRequest,
+        username_cooldown: Duration,
     ) -> Result<user::Data, DbErr> {
+        Mutation::ensure_username_free(db, &input.user.username, None, username_cooldown).await?;
+
         let user = db
             .user()
             .create(
@@ -142,25 +220,115 @@ impl Mutation {
         db: &PrismaClient,
         id: String,
         update: UpdateUser,
+        username_cooldown: Duration,
     ) -> Result<user::Data, DbErr> {
-        let vec_of_fields: Vec<SetParam> = [
-            update.user.email.map(user::email::set),
-            update.user.username.map(user::username::set),
-            update.user.bio.map(user::bio::set),
-            update.user.image.map(user::image::set),
-            update.user.password.map(user::password::set),
+        let current = db
+            .user()
+            .find_unique(user::id::equals(id.clone()))
+            .select(user::select!({ username version }))
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        let expected = update.user.version.unwrap_or(current.version);
+        if expected != current.version {
+            return Err(DbErr::Stale);
+        }
+
+        let renamed = match &update.user.username {
+            Patch::Value(x) if *x != current.username => {
+                Mutation::ensure_username_free(db, x, Some(&id), username_cooldown).await?;
+                true
+            }
+            _ => false,
+        };
+
+        let mut vec_of_fields: Vec<SetParam> = [
+            // A new address has to be verified again
+            update
+                .user
+                .email
+                .as_ref()
+                .value()
+                .map(|_| user::email_verified_at::set(None)),
+            update.user.email.value().map(user::email::set),
+            update.user.username.value().map(user::username::set),
+            update.user.password.value().map(user::password::set),
+            // Clearing falls back to the column's default
+            update
+                .user
+                .bio
+                .into_option()
+                .map(|x| user::bio::set(x.unwrap_or_default())),
+            update
+                .user
+                .image
+                .into_option()
+                .map(|x| user::image::set(x.unwrap_or_default())),
         ]
         .into_iter()
         .flatten()
         .collect();
 
-        let user = db
-            .user()
-            .update(user::id::equals(id), vec_of_fields)
+        vec_of_fields.push(user::version::increment(1));
+
+        let filters = vec![user::id::equals(id.clone()), user::version::equals(expected)];
+        let update = db.user().update_many(filters, vec_of_fields);
+
+        let updated = if renamed {
+            // The old name is only recorded while the user still has the version
+            // being edited, and the lock keeps it there until the update is done
+            let (_, updated) = db
+                ._batch((
+                    db._execute_raw(raw!(
+                        r#"INSERT INTO "UsernameChange" (id, username, "userId")
+                        SELECT {}, username, id FROM "User"
+                        WHERE id = {} AND version = {}
+                        FOR UPDATE"#,
+                        PrismaValue::String(Uuid::new_v4().to_string()),
+                        PrismaValue::String(id.clone()),
+                        PrismaValue::Int(expected.into())
+                    )),
+                    update,
+                ))
+                .await?;
+            updated
+        } else {
+            update.exec().await?
+        };
+        if updated == 0 {
+            return Err(DbErr::Stale);
+        }
+
+        db.user()
+            .find_unique(user::id::equals(id))
             .exec()
-            .await?;
+            .await?
+            .ok_or(DbErr::NotFound)
+    }
 
-        Ok(user)
+    // Old usernames stay reserved for whoever had them until the cooldown is over
+    async fn ensure_username_free(
+        db: &PrismaClient,
+        username: &str,
+        user_id: Option<&str>,
+        cooldown: Duration,
+    ) -> Result<(), DbErr> {
+        let since: DateTime<FixedOffset> = (Utc::now() - cooldown).into();
+
+        let mut filters = vec![
+            username_change::username::equals(username.to_string()),
+            username_change::created_at::gt(since),
+        ];
+        if let Some(id) = user_id {
+            filters.push(username_change::user_id::not(id.to_string()));
+        }
+
+        if db.username_change().count(filters).exec().await? > 0 {
+            return Err(DbErr::Conflict);
+        }
+
+        Ok(())
     }
 
     pub async fn follow_unfollow_user(
@@ -168,7 +336,7 @@ impl Mutation {
         user1_id: String,
         user2_id: String,
         follow: bool,
-    ) -> Result<Vec<String>, QueryError> {
+    ) -> Result<Option<notification_with_actor::Data>, QueryError> {
         let action = |id: String| {
             if follow {
                 user::follows::connect(vec![user::id::equals(id)])
@@ -177,14 +345,76 @@ impl Mutation {
             }
         };
 
-        let user = db
-            .user()
-            .update(user::id::equals(user1_id), vec![action(user2_id)])
-            .select(user::select!({ follows: select { id } }))
+        db.user()
+            .update(user::id::equals(user1_id.clone()), vec![action(user2_id.clone())])
+            .select(user::select!({ id }))
             .exec()
             .await?;
 
-        Ok(user.follows.into_iter().map(|x| x.id).collect())
+        let notification = if follow {
+            Mutation::notify_or_log(db, NotificationKind::Follow, user2_id, user1_id, None, None).await
+        } else {
+            None
+        };
+
+        Ok(notification)
+    }
+
+    // Blocking also removes any follow between the two users
+    pub async fn block_unblock_user(
+        db: &PrismaClient,
+        user_id: String,
+        target_id: String,
+        block: bool,
+    ) -> Result<(), QueryError> {
+        if !block {
+            db.user()
+                .update(
+                    user::id::equals(user_id),
+                    vec![user::blocking::disconnect(vec![user::id::equals(target_id)])],
+                )
+                .exec()
+                .await?;
+
+            return Ok(());
+        }
+
+        db._batch((
+            db.user().update(
+                user::id::equals(user_id.clone()),
+                vec![
+                    user::blocking::connect(vec![user::id::equals(target_id.clone())]),
+                    user::follows::disconnect(vec![user::id::equals(target_id.clone())]),
+                ],
+            ),
+            db.user().update(
+                user::id::equals(target_id),
+                vec![user::follows::disconnect(vec![user::id::equals(user_id)])],
+            ),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn mute_unmute_user(
+        db: &PrismaClient,
+        user_id: String,
+        target_id: String,
+        mute: bool,
+    ) -> Result<(), QueryError> {
+        let action = if mute {
+            user::muting::connect(vec![user::id::equals(target_id)])
+        } else {
+            user::muting::disconnect(vec![user::id::equals(target_id)])
+        };
+
+        db.user()
+            .update(user::id::equals(user_id), vec![action])
+            .exec()
+            .await?;
+
+        Ok(())
     }
 
     pub async fn update_article(
@@ -192,57 +422,121 @@ impl Mutation {
         update: UpdateArticle,
         slug: String,
         user_id: String,
-    ) -> Result<article_with_user::Data, DbErr> {
-        let vec_of_fields: Vec<article::SetParam> = [
+        moderator: bool,
+    ) -> Result<(article_with_user::Data, Vec<notification_with_actor::Data>), DbErr> {
+        // None of these can be cleared, the route turns away nulls
+        let body_changed = !update.article.body.is_absent();
+        let mut vec_of_fields: Vec<article::SetParam> = [
             update
                 .article.title
                 .as_ref()
+                .value()
                 .map(|x| article::slug::set(slug::slugify(x))),
-            update.article.title.map(article::title::set),
-            update.article.body.map(article::body::set),
-            update.article.description.map(article::description::set),
+            update.article.title.value().map(article::title::set),
+            update.article.body.value().map(article::body::set),
+            update.article.description.value().map(article::description::set),
         ]
         .into_iter()
         .flatten()
         .collect();
 
-        let article_id = db
+        vec_of_fields.push(article::version::increment(1));
+
+        let article = db
             .article()
-            .find_unique(article::slug::equals(slug.clone()))
+            .find_unique(article::slug::equals(slug))
             .select(article::select!({
+                id
+                version
                 user: select {
                     id
                 }
             }))
             .exec()
             .await?
-            .ok_or(DbErr::NotFound)?
-            .user
-            .id;
+            .ok_or(DbErr::NotFound)?;
 
-        if article_id != user_id {
+        let expected = update.article.version.unwrap_or(article.version);
+        if expected != article.version {
+            return Err(DbErr::Stale);
+        }
+
+        // The version and the owner are checked by the write itself, so neither
+        // can change between the read above and the update
+        let mut filters = vec![
+            article::id::equals(article.id.clone()),
+            article::version::equals(expected),
+        ];
+
+        let updated = if article.user.id == user_id {
+            filters.push(article::user_id::equals(user_id));
+            db.article().update_many(filters, vec_of_fields).exec().await?
+        } else if moderator {
+            // The audit row is only written while the article still has the version
+            // being edited, and the lock keeps it there until the update is done
+            let (_, updated) = db
+                ._batch((
+                    db._execute_raw(raw!(
+                        r#"INSERT INTO "AuditLog" (id, "actorId", action, "targetType", "targetId")
+                        SELECT {}, {}, 'article.update', 'article', id FROM "Article"
+                        WHERE id = {} AND version = {}
+                        FOR UPDATE"#,
+                        PrismaValue::String(Uuid::new_v4().to_string()),
+                        PrismaValue::String(user_id),
+                        PrismaValue::String(article.id.clone()),
+                        PrismaValue::Int(expected.into())
+                    )),
+                    db.article().update_many(filters, vec_of_fields),
+                ))
+                .await?;
+            updated
+        } else {
             return Err(DbErr::Unauthorized);
+        };
+
+        if updated == 0 {
+            return Err(DbErr::Stale);
         }
 
-        let article = db
+        let updated = db
             .article()
-            .update(article::slug::equals(slug), vec_of_fields)
+            .find_unique(article::id::equals(article.id))
             .include(article_with_user::include())
             .exec()
-            .await?;
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        if !body_changed {
+            return Ok((updated, vec![]));
+        }
+
+        // Mentions stay the author's, even when a moderator edits the body
+        let notifications =
+            Mutation::set_mentions(db, article.user.id, updated.id.clone(), None, &updated.body)
+                .await?;
+
+        let updated = db
+            .article()
+            .find_unique(article::id::equals(updated.id))
+            .include(article_with_user::include())
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
 
-        Ok(article)
+        Ok((updated, notifications))
     }
 
     pub async fn delete_article(
         db: &PrismaClient,
         slug: String,
         user_id: String,
+        moderator: bool,
     ) -> Result<(), DbErr> {
-        let article_id = db
+        let article = db
             .article()
             .find_unique(article::slug::equals(slug.clone()))
             .select(article::select!({
+                id
                 user: select {
                     id
                 }
@@ -250,18 +544,24 @@ impl Mutation {
             .exec()
             .await
             .map_err(DbErr::QueryError)?
-            .ok_or(DbErr::NotFound)?
-            .user
-            .id;
+            .ok_or(DbErr::NotFound)?;
+
+        let delete = db.article().delete(article::slug::equals(slug));
+
+        if article.user.id == user_id {
+            delete.exec().await?;
+            return Ok(());
+        }
 
-        if article_id != user_id {
+        if !moderator {
             return Err(DbErr::Unauthorized);
         }
 
-        db.article()
-            .delete(article::slug::equals(slug))
-            .exec()
-            .await?;
+        db._batch((
+            delete,
+            Mutation::audit(db, user_id, "article.delete", "article", article.id, None),
+        ))
+        .await?;
 
         Ok(())
     }
@@ -271,23 +571,70 @@ impl Mutation {
         slug: String,
         user_id: String,
         favorite: bool,
-    ) -> Result<article_with_user::Data, DbErr> {
-        let action = |id: String| {
-            if favorite {
-                article::favorites::connect(vec![user::id::equals(id)])
-            } else {
-                article::favorites::disconnect(vec![user::id::equals(id)])
-            }
+    ) -> Result<(article_with_user::Data, Option<notification_with_actor::Data>), DbErr> {
+        let target = db
+            .article()
+            .find_unique(article::slug::equals(slug.clone()))
+            .select(article::select!({ id }))
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        // The relation and the counter change in one statement, and the counter only
+        // moves if the row was really added or removed. Raw SQL leaves updatedAt alone.
+        let article_id = PrismaValue::String(target.id);
+        let user_id_value = PrismaValue::String(user_id.clone());
+        let query = if favorite {
+            raw!(
+                r#"WITH changed AS (
+                    INSERT INTO "_UserFavorites" ("A", "B") VALUES ({}, {})
+                    ON CONFLICT DO NOTHING
+                    RETURNING 1
+                )
+                UPDATE "Article" SET "favoritesCount" = "favoritesCount" + 1
+                WHERE id = {} AND EXISTS (SELECT 1 FROM changed)"#,
+                article_id.clone(),
+                user_id_value,
+                article_id
+            )
+        } else {
+            raw!(
+                r#"WITH changed AS (
+                    DELETE FROM "_UserFavorites" WHERE "A" = {} AND "B" = {}
+                    RETURNING 1
+                )
+                UPDATE "Article" SET "favoritesCount" = "favoritesCount" - 1
+                WHERE id = {} AND EXISTS (SELECT 1 FROM changed)"#,
+                article_id.clone(),
+                user_id_value,
+                article_id
+            )
         };
+        let changed = db._execute_raw(query).exec().await? > 0;
 
         let article = db
             .article()
-            .update(article::slug::equals(slug), vec![action(user_id)])
+            .find_unique(article::slug::equals(slug))
             .include(article_with_user::include())
             .exec()
-            .await?;
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        let notification = if favorite && changed {
+            Mutation::notify_or_log(
+                db,
+                NotificationKind::Favorite,
+                article.user.id.clone(),
+                user_id,
+                Some(article.id.clone()),
+                None,
+            )
+            .await
+        } else {
+            None
+        };
 
-        Ok(article)
+        Ok((article, notification))
     }
 
     pub async fn create_comment(
@@ -295,26 +642,78 @@ impl Mutation {
         input: NewComment,
         slug: String,
         user_id: String,
-    ) -> Result<comment_with_author::Data, DbErr> {
-        let comment = db
-            .comment()
-            .create(
-                article::slug::equals(slug),
-                input.comment.body,
-                user::id::equals(user_id),
-                vec![],
-            )
-            .include(comment_with_author::include())
+    ) -> Result<(comment_with_author::Data, Vec<notification_with_actor::Data>), DbErr> {
+        let article = db
+            .article()
+            .find_unique(article::slug::equals(slug))
+            .select(article::select!({ id user_id }))
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        let mentioned = db
+            .user()
+            .find_many(mentioned_users(&user_id, &input.comment.body))
+            .select(mentioned_user::select())
             .exec()
             .await?;
 
-        Ok(comment)
+        let id = Uuid::new_v4().to_string();
+        let mentions = mentioned
+            .iter()
+            .map(|x| {
+                mention::create_unchecked(
+                    x.username.clone(),
+                    x.id.clone(),
+                    vec![mention::comment_id::set(Some(id.clone()))],
+                )
+            })
+            .collect();
+
+        let (_, _, _, comment) = db
+            ._batch((
+                db.comment().create(
+                    article::id::equals(article.id.clone()),
+                    input.comment.body,
+                    user::id::equals(user_id.clone()),
+                    vec![comment::id::set(id.clone())],
+                ),
+                db._execute_raw(raw!(
+                    r#"UPDATE "Article" SET "commentsCount" = "commentsCount" + 1 WHERE id = {}"#,
+                    PrismaValue::String(article.id.clone())
+                )),
+                db.mention().create_many(mentions),
+                db.comment()
+                    .find_unique(comment::id::equals(id.clone()))
+                    .include(comment_with_author::include()),
+            ))
+            .await?;
+        let comment = comment.ok_or(DbErr::NotFound)?;
+
+        let mut notifications: Vec<_> = Mutation::notify_or_log(
+            db,
+            NotificationKind::Comment,
+            article.user_id,
+            user_id.clone(),
+            Some(article.id.clone()),
+            Some(id.clone()),
+        )
+        .await
+        .into_iter()
+        .collect();
+
+        notifications.extend(
+            Mutation::notify_mentioned(db, user_id, article.id, Some(id), mentioned).await,
+        );
+
+        Ok((comment, notifications))
     }
 
     pub async fn delete_comment(
         db: &PrismaClient,
         id: String,
         user_id: String,
+        moderator: bool,
     ) -> Result<(), DbErr> {
         let comment = db
             .comment()
@@ -323,16 +722,1157 @@ impl Mutation {
             .await?;
 
         if let Some(comment) = comment {
-            if comment.user_id != user_id {
+            let delete = db.comment().delete(comment::id::equals(comment.id.clone()));
+            // Raw so the article's updatedAt stays put
+            let count = db._execute_raw(raw!(
+                r#"UPDATE "Article" SET "commentsCount" = "commentsCount" - 1 WHERE id = {}"#,
+                PrismaValue::String(comment.article_id.clone())
+            ));
+
+            if comment.user_id == user_id {
+                db._batch((delete, count)).await?;
+                return Ok(());
+            }
+
+            if !moderator {
                 return Err(DbErr::Unauthorized);
             }
 
-            db.comment()
-                .delete(comment::id::equals(comment.id))
-                .exec()
-                .await?;
+            db._batch((
+                delete,
+                count,
+                Mutation::audit(db, user_id, "comment.delete", "comment", comment.id, None),
+            ))
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_password_reset(
+        db: &PrismaClient,
+        user_id: String,
+        token_hash: String,
+        expires_at: DateTime<FixedOffset>,
+    ) -> Result<(), DbErr> {
+        db.password_reset()
+            .create(token_hash, expires_at, user::id::equals(user_id), vec![])
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    // The unique key makes sure only one of several concurrent duplicates gets to
+    // run. A claim left behind by a request that never finished can be taken over
+    // once it's older than `abandoned_after`.
+    pub async fn claim_idempotency_key(
+        db: &PrismaClient,
+        user_id: String,
+        key: String,
+        request_hash: String,
+        ttl: Duration,
+        abandoned_after: Duration,
+    ) -> Result<IdempotencyClaim, QueryError> {
+        let now = Utc::now();
+
+        db.idempotency_key()
+            .delete_many(vec![
+                idempotency_key::user_id::equals(user_id.clone()),
+                idempotency_key::expires_at::lt(now.into()),
+            ])
+            .exec()
+            .await?;
+
+        let created = db
+            .idempotency_key()
+            .create(
+                key.clone(),
+                request_hash.clone(),
+                (now + ttl).into(),
+                user::id::equals(user_id.clone()),
+                vec![],
+            )
+            .exec()
+            .await;
+
+        match created {
+            Ok(_) => return Ok(IdempotencyClaim::Claimed),
+            Err(e) if e.is_prisma_error::<UniqueKeyViolation>() => {}
+            Err(e) => return Err(e),
+        }
+
+        let existing = db
+            .idempotency_key()
+            .find_unique(idempotency_key::user_id_key(user_id, key))
+            .exec()
+            .await?;
+
+        // Released by a failed request in the meantime, the client can just retry
+        let existing = match existing {
+            Some(x) => x,
+            None => return Ok(IdempotencyClaim::InFlight),
+        };
+
+        if existing.request_hash != request_hash {
+            return Ok(IdempotencyClaim::Mismatch);
+        }
+
+        if let (Some(status), Some(body)) = (existing.status, existing.body) {
+            return Ok(IdempotencyClaim::Completed { status, body });
+        }
+
+        if existing.created_at > now - abandoned_after {
+            return Ok(IdempotencyClaim::InFlight);
+        }
+
+        let taken = db
+            .idempotency_key()
+            .update_many(
+                vec![
+                    idempotency_key::id::equals(existing.id),
+                    idempotency_key::created_at::equals(existing.created_at),
+                    idempotency_key::status::equals(None),
+                ],
+                vec![idempotency_key::created_at::set(now.into())],
+            )
+            .exec()
+            .await?;
+
+        Ok(if taken == 1 {
+            IdempotencyClaim::Claimed
+        } else {
+            IdempotencyClaim::InFlight
+        })
+    }
+
+    pub async fn complete_idempotency_key(
+        db: &PrismaClient,
+        user_id: String,
+        key: String,
+        status: i32,
+        body: String,
+    ) -> Result<(), QueryError> {
+        db.idempotency_key()
+            .update(
+                idempotency_key::user_id_key(user_id, key),
+                vec![
+                    idempotency_key::status::set(Some(status)),
+                    idempotency_key::body::set(Some(body)),
+                ],
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    // Failed requests aren't replayed, the client may retry them with the same key
+    pub async fn release_idempotency_key(
+        db: &PrismaClient,
+        user_id: String,
+        key: String,
+    ) -> Result<(), QueryError> {
+        db.idempotency_key()
+            .delete_many(vec![
+                idempotency_key::user_id::equals(user_id),
+                idempotency_key::key::equals(key),
+                idempotency_key::status::equals(None),
+            ])
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    // Consumes a reset token and sets the new (already hashed) password.
+    // The token is marked as used with a conditional update, so two
+    // concurrent requests can't both redeem it.
+    pub async fn reset_password(
+        db: &PrismaClient,
+        token_hash: String,
+        password: String,
+    ) -> Result<user::Data, DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        let reset = db
+            .password_reset()
+            .find_first(vec![
+                password_reset::token_hash::equals(token_hash),
+                password_reset::used_at::equals(None),
+                password_reset::expires_at::gt(now),
+            ])
+            .exec()
+            .await?
+            .ok_or(DbErr::Unauthorized)?;
+
+        let consumed = db
+            .password_reset()
+            .update_many(
+                vec![
+                    password_reset::id::equals(reset.id),
+                    password_reset::used_at::equals(None),
+                ],
+                vec![password_reset::used_at::set(Some(now))],
+            )
+            .exec()
+            .await?;
+
+        if consumed == 0 {
+            return Err(DbErr::Unauthorized);
         }
 
+        // Any other outstanding token for the user is useless from now on
+        let (user, _) = db
+            ._batch((
+                db.user().update(
+                    user::id::equals(reset.user_id.clone()),
+                    vec![
+                        user::password::set(password),
+                        user::password_reset_required::set(false),
+                        // Whoever had the old password may still hold a session
+                        user::sessions_revoked_at::set(Some(now)),
+                    ],
+                ),
+                db.password_reset().delete_many(vec![
+                    password_reset::user_id::equals(reset.user_id),
+                    password_reset::used_at::equals(None),
+                ]),
+            ))
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn create_email_verification(
+        db: &PrismaClient,
+        user_id: String,
+        email: String,
+        token_hash: String,
+        expires_at: DateTime<FixedOffset>,
+    ) -> Result<(), DbErr> {
+        db.email_verification()
+            .create(token_hash, email, expires_at, user::id::equals(user_id), vec![])
+            .exec()
+            .await?;
+
         Ok(())
     }
+
+    pub async fn verify_email(db: &PrismaClient, token_hash: String) -> Result<user::Data, DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        let verification = db
+            .email_verification()
+            .find_first(vec![
+                email_verification::token_hash::equals(token_hash),
+                email_verification::expires_at::gt(now),
+            ])
+            .include(email_verification::include!({ user }))
+            .exec()
+            .await?
+            .ok_or(DbErr::Unauthorized)?;
+
+        // The user changed their email after this token was sent
+        if verification.email != verification.user.email {
+            return Err(DbErr::Unauthorized);
+        }
+
+        let (user, _) = db
+            ._batch((
+                db.user().update(
+                    user::id::equals(verification.user_id.clone()),
+                    vec![user::email_verified_at::set(Some(now))],
+                ),
+                db.email_verification()
+                    .delete_many(vec![email_verification::user_id::equals(verification.user_id)]),
+            ))
+            .await?;
+
+        Ok(user)
+    }
+
+    // Starting over discards any previous secret, enabled or not
+    pub async fn start_totp_enrollment(
+        db: &PrismaClient,
+        user_id: String,
+        secret: String,
+    ) -> Result<(), DbErr> {
+        db.user()
+            .update(
+                user::id::equals(user_id),
+                vec![
+                    user::totp_secret::set(Some(secret)),
+                    user::totp_enabled_at::set(None),
+                    user::totp_last_step::set(None),
+                ],
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn enable_totp(
+        db: &PrismaClient,
+        user_id: String,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        // All at once, so 2FA is never on without recovery codes
+        db._batch((
+            db.user().update(
+                user::id::equals(user_id.clone()),
+                vec![user::totp_enabled_at::set(Some(now))],
+            ),
+            db.recovery_code()
+                .delete_many(vec![recovery_code::user_id::equals(user_id.clone())]),
+            db.recovery_code().create_many(
+                recovery_code_hashes
+                    .into_iter()
+                    .map(|x| recovery_code::create_unchecked(x, user_id.clone(), vec![]))
+                    .collect(),
+            ),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn disable_totp(db: &PrismaClient, user_id: String) -> Result<(), DbErr> {
+        db._batch((
+            db.user().update(
+                user::id::equals(user_id.clone()),
+                vec![
+                    user::totp_secret::set(None),
+                    user::totp_enabled_at::set(None),
+                    user::totp_last_step::set(None),
+                ],
+            ),
+            db.recovery_code()
+                .delete_many(vec![recovery_code::user_id::equals(user_id)]),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    // Records the time step of an accepted TOTP code. Returns false if that
+    // step (or a later one) was already used, so a code can't be replayed.
+    pub async fn use_totp_step(db: &PrismaClient, user_id: String, step: i32) -> Result<bool, DbErr> {
+        let updated = db
+            .user()
+            .update_many(
+                vec![
+                    user::id::equals(user_id),
+                    or(vec![
+                        user::totp_last_step::equals(None),
+                        user::totp_last_step::lt(step),
+                    ]),
+                ],
+                vec![user::totp_last_step::set(Some(step))],
+            )
+            .exec()
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    pub async fn create_login_challenge(
+        db: &PrismaClient,
+        user_id: String,
+        expires_at: DateTime<FixedOffset>,
+    ) -> Result<String, DbErr> {
+        let challenge = db
+            .login_challenge()
+            .create(expires_at, user::id::equals(user_id), vec![])
+            .exec()
+            .await?;
+
+        Ok(challenge.id)
+    }
+
+    // Counts a code tried against the challenge. Returns false once it's
+    // expired, used up or gone, so it can't be guessed against forever.
+    pub async fn use_login_challenge_attempt(
+        db: &PrismaClient,
+        id: String,
+        user_id: String,
+        max_attempts: i32,
+    ) -> Result<bool, DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        let updated = db
+            .login_challenge()
+            .update_many(
+                vec![
+                    login_challenge::id::equals(id),
+                    login_challenge::user_id::equals(user_id),
+                    login_challenge::attempts::lt(max_attempts),
+                    login_challenge::expires_at::gt(now),
+                ],
+                vec![login_challenge::attempts::increment(1)],
+            )
+            .exec()
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    // A challenge is good for one login, expired ones are cleaned up with it
+    pub async fn finish_login_challenge(
+        db: &PrismaClient,
+        id: String,
+        user_id: String,
+    ) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        db.login_challenge()
+            .delete_many(vec![
+                login_challenge::user_id::equals(user_id),
+                or(vec![
+                    login_challenge::id::equals(id),
+                    login_challenge::expires_at::lt(now),
+                ]),
+            ])
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn use_recovery_code(db: &PrismaClient, id: String) -> Result<bool, DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        let updated = db
+            .recovery_code()
+            .update_many(
+                vec![
+                    recovery_code::id::equals(id),
+                    recovery_code::used_at::equals(None),
+                ],
+                vec![recovery_code::used_at::set(Some(now))],
+            )
+            .exec()
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    pub async fn create_access_token(
+        db: &PrismaClient,
+        user_id: String,
+        input: NewAccessToken,
+        token_hash: String,
+    ) -> Result<access_token::Data, DbErr> {
+        let token = db
+            .access_token()
+            .create(
+                input.token.name,
+                token_hash,
+                user::id::equals(user_id),
+                vec![
+                    access_token::scopes::set(
+                        input.token.scopes.iter().map(|x| x.to_string()).collect(),
+                    ),
+                    access_token::expires_at::set(input.token.expires_at),
+                ],
+            )
+            .exec()
+            .await?;
+
+        Ok(token)
+    }
+
+    pub async fn revoke_access_token(
+        db: &PrismaClient,
+        id: String,
+        user_id: String,
+    ) -> Result<(), DbErr> {
+        let deleted = db
+            .access_token()
+            .delete_many(vec![
+                access_token::id::equals(id),
+                access_token::user_id::equals(user_id),
+            ])
+            .exec()
+            .await?;
+
+        if deleted == 0 {
+            return Err(DbErr::NotFound);
+        }
+
+        Ok(())
+    }
+
+    pub async fn touch_access_token(db: &PrismaClient, id: String) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        db.access_token()
+            .update(
+                access_token::id::equals(id),
+                vec![access_token::last_used_at::set(Some(now))],
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn create_oidc_login(
+        db: &PrismaClient,
+        state: String,
+        code_verifier: String,
+        link_user_id: Option<String>,
+        expires_at: DateTime<FixedOffset>,
+    ) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        // Abandoned logins are cleaned up here instead of in a background job
+        db._batch((
+            db.oidc_login()
+                .delete_many(vec![oidc_login::expires_at::lt(now)]),
+            db.oidc_login().create(
+                state,
+                code_verifier,
+                expires_at,
+                vec![oidc_login::link_user_id::set(link_user_id)],
+            ),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    // A login can only be completed once
+    pub async fn take_oidc_login(
+        db: &PrismaClient,
+        state: String,
+    ) -> Result<oidc_login::Data, DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        let login = db
+            .oidc_login()
+            .find_unique(oidc_login::state::equals(state))
+            .exec()
+            .await?
+            .ok_or(DbErr::Unauthorized)?;
+
+        let deleted = db
+            .oidc_login()
+            .delete_many(vec![oidc_login::id::equals(login.id.clone())])
+            .exec()
+            .await?;
+
+        if deleted == 0 || login.expires_at < now {
+            return Err(DbErr::Unauthorized);
+        }
+
+        Ok(login)
+    }
+
+    pub async fn create_identity(
+        db: &PrismaClient,
+        user_id: String,
+        issuer: String,
+        subject: String,
+        email: Option<String>,
+    ) -> Result<(), DbErr> {
+        db.identity()
+            .create(
+                issuer,
+                subject,
+                user::id::equals(user_id),
+                vec![identity::email::set(email)],
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    // Users signing up through a provider never get to know their password,
+    // the caller passes the hash of a random one.
+    pub async fn create_oidc_user(
+        db: &PrismaClient,
+        username: String,
+        email: String,
+        password: String,
+        email_verified: bool,
+        issuer: String,
+        subject: String,
+    ) -> Result<user::Data, DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        // Together, so a failure can't leave an account nobody can log in to
+        let (user, _) = db
+            ._batch((
+                db.user().create(
+                    username,
+                    email.clone(),
+                    password,
+                    vec![user::email_verified_at::set(email_verified.then_some(now))],
+                ),
+                db.identity().create(
+                    issuer,
+                    subject,
+                    user::email::equals(email.clone()),
+                    vec![identity::email::set(Some(email))],
+                ),
+            ))
+            .await?;
+
+        Ok(user)
+    }
+
+    /// Builds the audit entry for an action taken with elevated privileges,
+    /// so it can be batched with the action itself.
+    pub fn audit<'a>(
+        db: &'a PrismaClient,
+        actor_id: String,
+        action: &str,
+        target_type: &str,
+        target_id: String,
+        details: Option<String>,
+    ) -> audit_log::Create<'a> {
+        db.audit_log().create(
+            actor_id,
+            action.to_string(),
+            target_type.to_string(),
+            target_id,
+            vec![audit_log::details::set(details)],
+        )
+    }
+
+    pub async fn set_user_role(
+        db: &PrismaClient,
+        actor_id: String,
+        username: String,
+        role: Role,
+    ) -> Result<user::Data, DbErr> {
+        let user_id = db
+            .user()
+            .find_unique(user::username::equals(username))
+            .select(user::select!({ id }))
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?
+            .id;
+
+        let (user, _) = db
+            ._batch((
+                db.user()
+                    .update(user::id::equals(user_id.clone()), vec![user::role::set(role)]),
+                Mutation::audit(
+                    db,
+                    actor_id,
+                    "user.role",
+                    "user",
+                    user_id,
+                    Some(format!("{role:?}")),
+                ),
+            ))
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn suspend_user(
+        db: &PrismaClient,
+        actor_id: String,
+        user_id: String,
+        reason: String,
+        until: Option<DateTime<FixedOffset>>,
+    ) -> Result<user::Data, DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+        let action = match until {
+            Some(_) => "user.suspend",
+            None => "user.ban",
+        };
+
+        let (user, _) = db
+            ._batch((
+                db.user().update(
+                    user::id::equals(user_id.clone()),
+                    vec![
+                        user::suspended_at::set(Some(now)),
+                        user::suspended_until::set(until),
+                        user::suspension_reason::set(Some(reason.clone())),
+                    ],
+                ),
+                Mutation::audit(db, actor_id, action, "user", user_id, Some(reason)),
+            ))
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn lift_suspension(
+        db: &PrismaClient,
+        actor_id: String,
+        user_id: String,
+    ) -> Result<user::Data, DbErr> {
+        let (user, _) = db
+            ._batch((
+                db.user().update(
+                    user::id::equals(user_id.clone()),
+                    vec![
+                        user::suspended_at::set(None),
+                        user::suspended_until::set(None),
+                        user::suspension_reason::set(None),
+                    ],
+                ),
+                Mutation::audit(db, actor_id, "user.unsuspend", "user", user_id, None),
+            ))
+            .await?;
+
+        Ok(user)
+    }
+
+    // Signs the user out everywhere and only lets them back in
+    // after choosing a new password with the reset token.
+    pub async fn force_password_reset(
+        db: &PrismaClient,
+        actor_id: String,
+        user_id: String,
+        token_hash: String,
+        expires_at: DateTime<FixedOffset>,
+    ) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        db._batch((
+            db.user().update(
+                user::id::equals(user_id.clone()),
+                vec![
+                    user::password_reset_required::set(true),
+                    user::sessions_revoked_at::set(Some(now)),
+                ],
+            ),
+            db.access_token()
+                .delete_many(vec![access_token::user_id::equals(user_id.clone())]),
+            db.password_reset()
+                .create(token_hash, expires_at, user::id::equals(user_id.clone()), vec![]),
+            Mutation::audit(db, actor_id, "user.password_reset", "user", user_id, None),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    // The report is rejected as a conflict if the user already reported the same content
+    pub async fn report_content(
+        db: &PrismaClient,
+        reporter_id: String,
+        reason: String,
+        target: ReportTarget,
+        threshold: i64,
+    ) -> Result<report::Data, DbErr> {
+        let content = match &target {
+            ReportTarget::Article(id) => report::article::connect(article::id::equals(id.clone())),
+            ReportTarget::Comment(id) => report::comment::connect(comment::id::equals(id.clone())),
+        };
+
+        // Hides the content once it has enough open reports, counting the new one
+        let hide = match target {
+            ReportTarget::Article(id) => db._execute_raw(raw!(
+                r#"UPDATE "Article" SET "hiddenAt" = NOW()
+                WHERE id = {} AND "hiddenAt" IS NULL
+                    AND (SELECT COUNT(*) FROM "Report" r WHERE r."articleId" = {} AND r.status = 'OPEN') >= {}"#,
+                PrismaValue::String(id.clone()),
+                PrismaValue::String(id),
+                PrismaValue::Int(threshold)
+            )),
+            ReportTarget::Comment(id) => db._execute_raw(raw!(
+                r#"UPDATE "Comment" SET "hiddenAt" = NOW()
+                WHERE id = {} AND "hiddenAt" IS NULL
+                    AND (SELECT COUNT(*) FROM "Report" r WHERE r."commentId" = {} AND r.status = 'OPEN') >= {}"#,
+                PrismaValue::String(id.clone()),
+                PrismaValue::String(id),
+                PrismaValue::Int(threshold)
+            )),
+        };
+
+        let (report, _) = db
+            ._batch((
+                db.report()
+                    .create(reason, user::id::equals(reporter_id), vec![content]),
+                hide,
+            ))
+            .await?;
+
+        Ok(report)
+    }
+
+    // Closes every open report on the content. Dismissing also shows
+    // the content again, in case the reports got it auto-hidden.
+    pub async fn resolve_reports(
+        db: &PrismaClient,
+        actor_id: String,
+        target: ReportTarget,
+        status: ReportStatus,
+    ) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+        let (action, hidden_at) = match status {
+            ReportStatus::Dismissed => ("report.dismiss", None),
+            _ => ("report.hide", Some(now)),
+        };
+
+        let reports = db.report().update_many(
+            vec![
+                target.report_filter(),
+                report::status::equals(ReportStatus::Open),
+            ],
+            vec![
+                report::status::set(status),
+                report::resolved_at::set(Some(now)),
+                report::resolved_by_id::set(Some(actor_id.clone())),
+            ],
+        );
+
+        match target {
+            ReportTarget::Article(id) => {
+                db._batch((
+                    db.article().update(
+                        article::id::equals(id.clone()),
+                        vec![article::hidden_at::set(hidden_at)],
+                    ),
+                    reports,
+                    Mutation::audit(db, actor_id, action, "article", id, None),
+                ))
+                .await?;
+            }
+            ReportTarget::Comment(id) => {
+                db._batch((
+                    db.comment().update(
+                        comment::id::equals(id.clone()),
+                        vec![comment::hidden_at::set(hidden_at)],
+                    ),
+                    reports,
+                    Mutation::audit(db, actor_id, action, "comment", id, None),
+                ))
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sets the stored counters back to what the relations say, for when they
+    // drifted from concurrent requests or manual edits. Returns the articles fixed.
+    pub async fn reconcile_counters(db: &PrismaClient) -> Result<i64, QueryError> {
+        // Column "A" of the implicit favorites table is the article
+        let fixed = db
+            ._execute_raw(raw!(
+                r#"UPDATE "Article" a SET
+                    "favoritesCount" = (SELECT COUNT(*) FROM "_UserFavorites" f WHERE f."A" = a.id),
+                    "commentsCount" = (SELECT COUNT(*) FROM "Comment" c WHERE c."articleId" = a.id)
+                WHERE "favoritesCount" <> (SELECT COUNT(*) FROM "_UserFavorites" f WHERE f."A" = a.id)
+                    OR "commentsCount" <> (SELECT COUNT(*) FROM "Comment" c WHERE c."articleId" = a.id)"#
+            ))
+            .exec()
+            .await?;
+
+        Ok(fixed)
+    }
+
+    // Links the @handles in a body to the users they name, replacing what it
+    // mentioned before. Users who block the author are left out, and only the
+    // ones that weren't mentioned yet get notified.
+    async fn set_mentions(
+        db: &PrismaClient,
+        author_id: String,
+        article_id: String,
+        comment_id: Option<String>,
+        body: &str,
+    ) -> Result<Vec<notification_with_actor::Data>, QueryError> {
+        let target = || match &comment_id {
+            Some(id) => mention::comment_id::equals(Some(id.clone())),
+            None => mention::article_id::equals(Some(article_id.clone())),
+        };
+
+        let (previous, users) = db
+            ._batch((
+                db.mention()
+                    .find_many(vec![target()])
+                    .select(mention::select!({ user_id })),
+                db.user()
+                    .find_many(mentioned_users(&author_id, body))
+                    .select(mentioned_user::select()),
+            ))
+            .await?;
+
+        let previous: Vec<String> = previous.into_iter().map(|x| x.user_id).collect();
+        let current: Vec<&str> = users.iter().map(|x| x.id.as_str()).collect();
+        let removed: Vec<String> = previous
+            .iter()
+            .filter(|x| !current.contains(&x.as_str()))
+            .cloned()
+            .collect();
+
+        db.mention()
+            .delete_many(vec![target(), mention::user_id::in_vec(removed)])
+            .exec()
+            .await?;
+
+        let added: Vec<_> = users.into_iter().filter(|x| !previous.contains(&x.id)).collect();
+
+        db._batch(
+            added
+                .iter()
+                .map(|x| {
+                    let link = match &comment_id {
+                        Some(id) => mention::comment::connect(comment::id::equals(id.clone())),
+                        None => mention::article::connect(article::id::equals(article_id.clone())),
+                    };
+                    db.mention().create(
+                        x.username.clone(),
+                        user::id::equals(x.id.clone()),
+                        vec![link],
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+        Ok(Mutation::notify_mentioned(db, author_id, article_id, comment_id, added).await)
+    }
+
+    async fn notify_mentioned(
+        db: &PrismaClient,
+        author_id: String,
+        article_id: String,
+        comment_id: Option<String>,
+        users: Vec<mentioned_user::Data>,
+    ) -> Vec<notification_with_actor::Data> {
+        let mut notifications = vec![];
+        for user in users {
+            let notification = Mutation::notify_or_log(
+                db,
+                NotificationKind::Mention,
+                user.id,
+                author_id.clone(),
+                Some(article_id.clone()),
+                comment_id.clone(),
+            )
+            .await;
+            notifications.extend(notification);
+        }
+
+        notifications
+    }
+
+    // Skipped for the user's own actions, for kinds the recipient muted, for actors
+    // they blocked or muted, and when an identical notification is still unread.
+    pub async fn notify(
+        db: &PrismaClient,
+        kind: NotificationKind,
+        recipient_id: String,
+        actor_id: String,
+        article_id: Option<String>,
+        comment_id: Option<String>,
+    ) -> Result<Option<notification_with_actor::Data>, QueryError> {
+        if recipient_id == actor_id {
+            return Ok(None);
+        }
+
+        let (recipient, ignored, unread) = db
+            ._batch((
+                db.user()
+                    .find_unique(user::id::equals(recipient_id.clone()))
+                    .select(user::select!({ muted_notifications })),
+                db.user().count(vec![
+                    user::id::equals(recipient_id.clone()),
+                    or(vec![
+                        user::blocking::some(vec![user::id::equals(actor_id.clone())]),
+                        user::muting::some(vec![user::id::equals(actor_id.clone())]),
+                    ]),
+                ]),
+                db.notification().count(vec![
+                    notification::recipient_id::equals(recipient_id.clone()),
+                    notification::actor_id::equals(actor_id.clone()),
+                    notification::kind::equals(kind),
+                    notification::article_id::equals(article_id.clone()),
+                    notification::comment_id::equals(comment_id.clone()),
+                    notification::read_at::equals(None),
+                ]),
+            ))
+            .await?;
+
+        let muted = recipient
+            .map(|x| x.muted_notifications.contains(&kind))
+            .unwrap_or(true);
+
+        if muted || ignored > 0 || unread > 0 {
+            return Ok(None);
+        }
+
+        let mut params = vec![];
+        if let Some(id) = article_id {
+            params.push(notification::article::connect(article::id::equals(id)));
+        }
+        if let Some(id) = comment_id {
+            params.push(notification::comment::connect(comment::id::equals(id)));
+        }
+
+        let notification = db
+            .notification()
+            .create(
+                kind,
+                user::id::equals(recipient_id),
+                user::id::equals(actor_id),
+                params,
+            )
+            .include(notification_with_actor::include())
+            .exec()
+            .await?;
+
+        Ok(Some(notification))
+    }
+
+    // Notifications are created after the action they're about was saved, so
+    // failing to create one is logged instead of failing the action
+    async fn notify_or_log(
+        db: &PrismaClient,
+        kind: NotificationKind,
+        recipient_id: String,
+        actor_id: String,
+        article_id: Option<String>,
+        comment_id: Option<String>,
+    ) -> Option<notification_with_actor::Data> {
+        Mutation::notify(db, kind, recipient_id, actor_id, article_id, comment_id)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Couldn't create a {kind:?} notification, {e}");
+                None
+            })
+    }
+
+    pub async fn mark_notification_read(
+        db: &PrismaClient,
+        user_id: String,
+        id: String,
+    ) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        let updated = db
+            .notification()
+            .update_many(
+                vec![
+                    notification::id::equals(id),
+                    notification::recipient_id::equals(user_id),
+                ],
+                vec![notification::read_at::set(Some(now))],
+            )
+            .exec()
+            .await?;
+
+        if updated == 0 {
+            return Err(DbErr::NotFound);
+        }
+
+        Ok(())
+    }
+
+    pub async fn mark_all_notifications_read(
+        db: &PrismaClient,
+        user_id: String,
+    ) -> Result<(), DbErr> {
+        let now: DateTime<FixedOffset> = Utc::now().into();
+
+        db.notification()
+            .update_many(
+                vec![
+                    notification::recipient_id::equals(user_id),
+                    notification::read_at::equals(None),
+                ],
+                vec![notification::read_at::set(Some(now))],
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_muted_notifications(
+        db: &PrismaClient,
+        user_id: String,
+        muted: Vec<NotificationKind>,
+    ) -> Result<Vec<NotificationKind>, DbErr> {
+        let user = db
+            .user()
+            .update(
+                user::id::equals(user_id),
+                vec![user::muted_notifications::set(muted)],
+            )
+            .select(user::select!({ muted_notifications }))
+            .exec()
+            .await?;
+
+        Ok(user.muted_notifications)
+    }
+}
+
+// The users named by the @handles in a body, except those blocking the author
+fn mentioned_users(author_id: &str, body: &str) -> Vec<user::WhereParam> {
+    vec![
+        user::username::in_vec(parse_mentions(body)),
+        user::blocking::none(vec![user::id::equals(author_id.to_string())]),
+    ]
+}
+
+/// The distinct names written as `@username` in a text. An `@` right after a
+/// letter or digit is part of something else, like an email address.
+pub fn parse_mentions(text: &str) -> Vec<String> {
+    let mut handles: Vec<String> = vec![];
+    let mut previous: Option<char> = None;
+
+    for (i, c) in text.char_indices() {
+        let starts = previous.map_or(true, |x| !(x.is_alphanumeric() || x == '_'));
+        previous = Some(c);
+        if c != '@' || !starts {
+            continue;
+        }
+
+        let handle: String = text[i + 1..]
+            .chars()
+            .take_while(|x| x.is_alphanumeric() || matches!(x, '_' | '-' | '.'))
+            .collect();
+        // A mention can end a sentence
+        let handle = handle.trim_end_matches('.');
+
+        if !handle.is_empty() && !handles.iter().any(|x| x == handle) {
+            handles.push(handle.to_string());
+        }
+        // Bounds the lookups a single body can cause
+        if handles.len() == 50 {
+            break;
+        }
+    }
+
+    handles
+}
+
+pub enum IdempotencyClaim {
+    // The caller runs the request and stores its response
+    Claimed,
+    Completed { status: i32, body: String },
+    InFlight,
+    // The key was used for a different request
+    Mismatch,
+}
+
+/// Content that can be reported, by id
+pub enum ReportTarget {
+    Article(String),
+    Comment(String),
+}
+
+impl ReportTarget {
+    fn report_filter(&self) -> report::WhereParam {
+        match self {
+            ReportTarget::Article(id) => report::article_id::equals(Some(id.clone())),
+            ReportTarget::Comment(id) => report::comment_id::equals(Some(id.clone())),
+        }
+    }
 }
@@ -1,12 +1,22 @@
-use prisma_client_rust::operator::or;
+use prisma_client_rust::{
+    chrono::{DateTime, FixedOffset, Utc},
+    operator::or,
+    raw, PrismaValue,
+};
 use types::{
-    article::{Params, Tags},
+    admin::{AdminUserBody, AuditLogEntry, SuspensionBody},
+    article::{Article, ArticleBody, Params, Tags},
     comment::{CommentBody},
-    user::{Profile, User, UserBody, ProfileBody},
+    notification::{NotificationBody, NotificationKind as NotificationKindBody},
+    report::{ReportBody, ReportStatus as ReportStatusBody},
+    user::{Mention, Profile, ProfileStats, Role as UserRole, User, UserBody, ProfileBody},
 };
 
 use crate::{
     prisma::{
+        access_token, audit_log, comment, identity, notification, recovery_code, report,
+        username_change,
+        NotificationKind, ReportStatus, Role,
         user::{self, Data as UserData},
         PrismaClient,
     },
@@ -14,28 +24,260 @@ use crate::{
 };
 
 use super::{
-    mutation::article_with_user,
-    prisma::article::{self, WhereParam},
+    mutation::{article_with_user, ArticleToJson},
+    prisma::article,
 };
 
-user::select!(user_favs_and_follows {
-    favorites: select {
-        id
+identity::include!(identity_with_user { user });
+
+user::select!(user_status {
+    role
+    suspended_at
+    suspended_until
+    sessions_revoked_at
+});
+
+access_token::include!(access_token_with_status {
+    user: select {
+        role
+        suspended_at
+        suspended_until
+    }
+});
+
+report::include!(report_with_content {
+    reporter: select {
+        username
+    }
+    article: select {
+        slug
+        hidden_at
+        user: select {
+            id
+            username
+        }
     }
-    follows: select {
+    comment: select {
         id
+        hidden_at
+        article: select {
+            slug
+        }
+        author: select {
+            id
+            username
+        }
     }
 });
 
-article::include!(article_comment_with_author {
-    comments: include {
-        author: include {
-            favorites
-            follows
+notification::include!(notification_with_actor {
+    actor: select {
+        username
+    }
+    article: select {
+        slug
+    }
+});
+
+article::include!((filters: Vec<comment::WhereParam>) => article_comment_with_author {
+    comments(filters): include {
+        author: select {
+            id
+            username
+            bio
+            image
+        }
+        mentions: include {
+            user: select {
+                username
+            }
         }
     }
 });
 
+impl From<Role> for UserRole {
+    fn from(value: Role) -> Self {
+        match value {
+            Role::User => UserRole::User,
+            Role::Moderator => UserRole::Moderator,
+            Role::Admin => UserRole::Admin,
+        }
+    }
+}
+
+impl From<ReportStatus> for ReportStatusBody {
+    fn from(value: ReportStatus) -> Self {
+        match value {
+            ReportStatus::Open => ReportStatusBody::Open,
+            ReportStatus::Dismissed => ReportStatusBody::Dismissed,
+            ReportStatus::Resolved => ReportStatusBody::Resolved,
+        }
+    }
+}
+
+impl From<ReportStatusBody> for ReportStatus {
+    fn from(value: ReportStatusBody) -> Self {
+        match value {
+            ReportStatusBody::Open => ReportStatus::Open,
+            ReportStatusBody::Dismissed => ReportStatus::Dismissed,
+            ReportStatusBody::Resolved => ReportStatus::Resolved,
+        }
+    }
+}
+
+impl From<NotificationKind> for NotificationKindBody {
+    fn from(value: NotificationKind) -> Self {
+        match value {
+            NotificationKind::Follow => NotificationKindBody::Follow,
+            NotificationKind::Favorite => NotificationKindBody::Favorite,
+            NotificationKind::Comment => NotificationKindBody::Comment,
+            NotificationKind::Mention => NotificationKindBody::Mention,
+        }
+    }
+}
+
+impl From<NotificationKindBody> for NotificationKind {
+    fn from(value: NotificationKindBody) -> Self {
+        match value {
+            NotificationKindBody::Follow => NotificationKind::Follow,
+            NotificationKindBody::Favorite => NotificationKind::Favorite,
+            NotificationKindBody::Comment => NotificationKind::Comment,
+            NotificationKindBody::Mention => NotificationKind::Mention,
+        }
+    }
+}
+
+impl From<UserRole> for Role {
+    fn from(value: UserRole) -> Self {
+        match value {
+            UserRole::User => Role::User,
+            UserRole::Moderator => Role::Moderator,
+            UserRole::Admin => Role::Admin,
+        }
+    }
+}
+
+// Makes `%`, `_` and `\` match themselves in a LIKE pattern
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Which of the articles and authors on a page the viewer favorited and follows
+#[derive(Debug, Default)]
+pub struct ViewerFlags {
+    favorited: Vec<String>,
+    following: Vec<String>,
+}
+
+impl ViewerFlags {
+    pub fn favorited(&self, article_id: &str) -> bool {
+        self.favorited.iter().any(|x| x == article_id)
+    }
+
+    pub fn following(&self, user_id: &str) -> bool {
+        self.following.iter().any(|x| x == user_id)
+    }
+}
+
+/// No avatar is stored as an empty string, but always sent as null
+pub fn avatar(image: String) -> Option<String> {
+    (!image.is_empty()).then_some(image)
+}
+
+/// Matches users who aren't suspended or banned right now
+pub fn active_user() -> user::WhereParam {
+    let now: DateTime<FixedOffset> = Utc::now().into();
+    or(vec![
+        user::suspended_at::equals(None),
+        user::suspended_until::lt(now),
+    ])
+}
+
+/// Matches users whose content `viewer` wants to see, i.e. not muted or blocked by them
+pub fn not_muted_by(viewer: &str) -> Vec<user::WhereParam> {
+    vec![
+        user::muted_by::none(vec![user::id::equals(viewer.to_string())]),
+        user::blocked_by::none(vec![user::id::equals(viewer.to_string())]),
+    ]
+}
+
+pub fn is_suspended(
+    suspended_at: Option<DateTime<FixedOffset>>,
+    suspended_until: Option<DateTime<FixedOffset>>,
+) -> bool {
+    match (suspended_at, suspended_until) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(_), Some(until)) => until > Utc::now(),
+    }
+}
+
+impl report_with_content::Data {
+    /// Id and username of whoever wrote the reported content
+    pub fn author(&self) -> Option<(&str, &str)> {
+        match (&self.article, &self.comment) {
+            (Some(article), _) => Some((&article.user.id, &article.user.username)),
+            (_, Some(comment)) => Some((&comment.author.id, &comment.author.username)),
+            _ => None,
+        }
+    }
+
+    pub fn into_report_body(self) -> ReportBody {
+        let author = self.author().map(|x| x.1.to_string()).unwrap_or_default();
+        let (article, comment, content_hidden) = match (self.article, self.comment) {
+            (Some(article), _) => (article.slug, None, article.hidden_at.is_some()),
+            (_, Some(comment)) => (
+                comment.article.slug,
+                Some(comment.id),
+                comment.hidden_at.is_some(),
+            ),
+            _ => (String::new(), None, false),
+        };
+
+        ReportBody {
+            id: self.id,
+            reason: self.reason,
+            status: self.status.into(),
+            created_at: self.created_at,
+            reporter: self.reporter.username,
+            author,
+            article,
+            comment,
+            content_hidden,
+        }
+    }
+}
+
+impl notification_with_actor::Data {
+    pub fn into_notification_body(self) -> NotificationBody {
+        NotificationBody {
+            id: self.id,
+            kind: self.kind.into(),
+            created_at: self.created_at,
+            read: self.read_at.is_some(),
+            actor: self.actor.username,
+            article: self.article.map(|x| x.slug),
+            comment: self.comment_id,
+        }
+    }
+}
+
+impl audit_log::Data {
+    pub fn into_audit_log_entry(self) -> AuditLogEntry {
+        AuditLogEntry {
+            id: self.id,
+            actor_id: self.actor_id,
+            action: self.action,
+            target_type: self.target_type,
+            target_id: self.target_id,
+            details: self.details,
+            created_at: self.created_at,
+        }
+    }
+}
+
 impl UserData {
     pub fn into_user(self, token: String) -> User {
         User {
@@ -44,17 +286,43 @@ impl UserData {
                 token,
                 username: self.username,
                 bio: self.bio,
-                image: Some(self.image),
+                image: avatar(self.image),
+                email_verified: self.email_verified_at.is_some(),
+                role: self.role.into(),
+                version: self.version,
             },
         }
     }
+    pub fn into_admin_user(self) -> AdminUserBody {
+        let suspension = match is_suspended(self.suspended_at, self.suspended_until) {
+            true => self.suspended_at.map(|suspended_at| SuspensionBody {
+                reason: self.suspension_reason.unwrap_or_default(),
+                suspended_at,
+                expires_at: self.suspended_until,
+            }),
+            false => None,
+        };
+
+        AdminUserBody {
+            id: self.id,
+            username: self.username,
+            email: self.email,
+            role: self.role.into(),
+            email_verified: self.email_verified_at.is_some(),
+            password_reset_required: self.password_reset_required,
+            created_at: self.created_at,
+            suspension,
+        }
+    }
+
     pub fn into_profile(self, following: bool) -> Profile {
         Profile {
             profile: ProfileBody {
                 username: self.username,
                 bio: self.bio,
-                image: Some(self.image),
+                image: avatar(self.image),
                 following,
+                stats: None,
             },
         }
     }
@@ -71,10 +339,16 @@ impl article_comment_with_author::comments::Data {
                 profile: ProfileBody { 
                     username: self.author.username,
                     bio: self.author.bio,
-                    image: Some(self.author.image),
+                    image: avatar(self.author.image),
                     following,
+                    stats: None,
                  },
             },
+            mentions: self
+                .mentions
+                .into_iter()
+                .map(|x| Mention { handle: x.handle, username: x.user.username })
+                .collect(),
         }
     }
 }
@@ -82,6 +356,12 @@ impl article_comment_with_author::comments::Data {
 pub struct Query;
 
 impl Query {
+    // Cheapest round trip there is, for readiness checks
+    pub async fn ping(db: &PrismaClient) -> Result<(), DbErr> {
+        db._execute_raw(raw!("SELECT 1")).exec().await?;
+        Ok(())
+    }
+
     pub async fn get_user_by_id(db: &PrismaClient, id: String) -> Result<UserData, DbErr> {
         let user = db
             .user()
@@ -104,28 +384,44 @@ impl Query {
         Ok(user)
     }
 
+    // Old usernames resolve to the last user who had them, so callers
+    // should compare the username they get back to the one they asked for
     pub async fn get_user_by_username(
         db: &PrismaClient,
         username: String,
     ) -> Result<UserData, DbErr> {
         let user = db
             .user()
-            .find_unique(user::username::equals(username))
+            .find_unique(user::username::equals(username.clone()))
+            .exec()
+            .await?;
+
+        if let Some(user) = user {
+            return Ok(user);
+        }
+
+        let change = db
+            .username_change()
+            .find_first(vec![username_change::username::equals(username)])
+            .order_by(username_change::created_at::order(
+                prisma_client_rust::Direction::Desc,
+            ))
+            .include(username_change::include!({ user }))
             .exec()
             .await?
             .ok_or(DbErr::NotFound)?;
 
-        Ok(user)
+        Ok(change.user)
     }
 
-    pub async fn get_user_favs_and_follows(
+    // For acting on an account, where an old username must not stand in for it
+    pub async fn get_user_by_current_username(
         db: &PrismaClient,
-        id: String,
-    ) -> Result<user_favs_and_follows::Data, DbErr> {
+        username: String,
+    ) -> Result<UserData, DbErr> {
         let user = db
             .user()
-            .find_unique(user::id::equals(id))
-            .select(user_favs_and_follows::select())
+            .find_unique(user::username::equals(username))
             .exec()
             .await?
             .ok_or(DbErr::NotFound)?;
@@ -133,6 +429,83 @@ impl Query {
         Ok(user)
     }
 
+    // Lets `?author=` and `?favorited=` keep working with old usernames
+    async fn resolve_user_id(db: &PrismaClient, username: String) -> Result<Option<String>, DbErr> {
+        match Query::get_user_by_username(db, username).await {
+            Ok(user) => Ok(Some(user.id)),
+            Err(DbErr::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Only looks at the articles and authors asked about, so it costs the same
+    // however many articles the viewer favorited or users they follow
+    pub async fn get_viewer_flags(
+        db: &PrismaClient,
+        viewer: Option<String>,
+        article_ids: Vec<String>,
+        author_ids: Vec<String>,
+    ) -> Result<ViewerFlags, DbErr> {
+        let viewer = match viewer {
+            Some(viewer) => viewer,
+            None => return Ok(ViewerFlags::default()),
+        };
+
+        let (favorited, following) = db
+            ._batch((
+                db.article()
+                    .find_many(vec![
+                        article::id::in_vec(article_ids),
+                        article::favorites::some(vec![user::id::equals(viewer.clone())]),
+                    ])
+                    .select(article::select!({ id })),
+                db.user()
+                    .find_many(vec![
+                        user::id::in_vec(author_ids),
+                        user::followers::some(vec![user::id::equals(viewer)]),
+                    ])
+                    .select(user::select!({ id })),
+            ))
+            .await?;
+
+        Ok(ViewerFlags {
+            favorited: favorited.into_iter().map(|x| x.id).collect(),
+            following: following.into_iter().map(|x| x.id).collect(),
+        })
+    }
+
+    pub async fn with_viewer_flags(
+        db: &PrismaClient,
+        articles: Vec<article_with_user::Data>,
+        viewer: Option<String>,
+    ) -> Result<Vec<ArticleBody>, DbErr> {
+        let article_ids = articles.iter().map(|x| x.id.clone()).collect();
+        let author_ids = articles.iter().map(|x| x.user.id.clone()).collect();
+        let flags = Query::get_viewer_flags(db, viewer, article_ids, author_ids).await?;
+
+        let articles = articles
+            .into_iter()
+            .map(|x| {
+                let (following, favorited) = (flags.following(&x.user.id), flags.favorited(&x.id));
+                x.into_article_body(following, favorited)
+            })
+            .collect();
+
+        Ok(articles)
+    }
+
+    pub async fn article_for_viewer(
+        db: &PrismaClient,
+        article: article_with_user::Data,
+        viewer: Option<String>,
+    ) -> Result<Article, DbErr> {
+        let article = Query::with_viewer_flags(db, vec![article], viewer)
+            .await?
+            .remove(0);
+
+        Ok(Article { article })
+    }
+
     pub async fn get_user_follows_by_id(
         db: &PrismaClient,
         id: String,
@@ -170,15 +543,30 @@ impl Query {
     pub async fn get_articles(
         db: &PrismaClient,
         params: Params,
-    ) -> Result<Vec<article_with_user::Data>, DbErr> {
+        viewer: Option<String>,
+    ) -> Result<Vec<ArticleBody>, DbErr> {
+        let author = match params.author {
+            Some(x) => match Query::resolve_user_id(db, x).await? {
+                Some(id) => Some(article::user_id::equals(id)),
+                None => return Ok(vec![]),
+            },
+            None => None,
+        };
+        let favorited = match params.favorited {
+            Some(x) => match Query::resolve_user_id(db, x).await? {
+                Some(id) => Some(article::favorites::some(vec![user::id::equals(id)])),
+                None => return Ok(vec![]),
+            },
+            None => None,
+        };
+
         let vec_of_params: Vec<article::WhereParam> = [
-            params
-                .author
-                .map(|x| article::user::is(vec![user::username::equals(x)])),
-            params
-                .favorited
-                .map(|x| article::favorites::some(vec![user::username::equals(x)])),
+            author,
+            favorited,
             params.tag.map(|x| article::tag_list::has_some(vec![x])),
+            Some(article::user::is(vec![active_user()])),
+            Some(article::hidden_at::equals(None)),
+            viewer.as_ref().map(|x| article::user::is(not_muted_by(x))),
         ]
         .into_iter()
         .flatten()
@@ -196,25 +584,26 @@ impl Query {
             .exec()
             .await?;
 
-        Ok(articles)
+        Query::with_viewer_flags(db, articles, viewer).await
     }
 
     pub async fn get_followed_articles(
         db: &PrismaClient,
         user_id: String,
         query_params: Params,
-    ) -> Result<Vec<article_with_user::Data>, DbErr> {
-        let user = Query::get_user_follows_by_id(db, user_id).await?;
-        let params: Vec<WhereParam> = user
-            .into_iter()
-            .map(|x| article::user::is(vec![user::id::equals(x)]))
-            .collect();
-
+    ) -> Result<Vec<ArticleBody>, DbErr> {
         let articles = db
             .article()
-            .find_many(vec![or(params)])
+            .find_many(vec![
+                article::user::is(vec![
+                    user::followers::some(vec![user::id::equals(user_id.clone())]),
+                    active_user(),
+                ]),
+                article::hidden_at::equals(None),
+                article::user::is(not_muted_by(&user_id)),
+            ])
             .order_by(article::created_at::order(
-                prisma_client_rust::Direction::Asc,
+                prisma_client_rust::Direction::Desc,
             ))
             .skip(query_params.offset.unwrap_or(0))
             .take(query_params.limit.unwrap_or(20))
@@ -222,7 +611,7 @@ impl Query {
             .exec()
             .await?;
 
-        Ok(articles)
+        Query::with_viewer_flags(db, articles, Some(user_id)).await
     }
 
     pub async fn get_tags(db: &PrismaClient) -> Result<Tags, DbErr> {
@@ -241,15 +630,471 @@ impl Query {
     pub async fn get_comments_from_article(
         db: &PrismaClient,
         slug: String,
-    ) -> Result<Vec<article_comment_with_author::comments::Data>, DbErr> {
+        viewer: Option<String>,
+    ) -> Result<Vec<CommentBody>, DbErr> {
+        let mut filters = vec![
+            comment::author::is(vec![active_user()]),
+            comment::hidden_at::equals(None),
+        ];
+        if let Some(viewer) = &viewer {
+            filters.push(comment::author::is(not_muted_by(viewer)));
+        }
+
         let comments = db
             .article()
             .find_unique(article::slug::equals(slug))
-            .include(article_comment_with_author::include())
+            .include(article_comment_with_author::include(filters))
             .exec()
             .await?
             .ok_or(DbErr::NotFound)?;
 
-        Ok(comments.comments)
+        let author_ids = comments.comments.iter().map(|x| x.author.id.clone()).collect();
+        let flags = Query::get_viewer_flags(db, viewer, vec![], author_ids).await?;
+
+        let comments = comments
+            .comments
+            .into_iter()
+            .map(|x| {
+                let following = flags.following(&x.author.id);
+                x.into_comment_body(following)
+            })
+            .collect();
+
+        Ok(comments)
+    }
+
+    pub async fn is_comment_on_article(
+        db: &PrismaClient,
+        comment_id: String,
+        slug: String,
+    ) -> Result<bool, DbErr> {
+        let count = db
+            .comment()
+            .count(vec![
+                comment::id::equals(comment_id),
+                comment::article::is(vec![article::slug::equals(slug)]),
+            ])
+            .exec()
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    pub async fn get_unused_recovery_codes(
+        db: &PrismaClient,
+        user_id: String,
+    ) -> Result<Vec<recovery_code::Data>, DbErr> {
+        let codes = db
+            .recovery_code()
+            .find_many(vec![
+                recovery_code::user_id::equals(user_id),
+                recovery_code::used_at::equals(None),
+            ])
+            .exec()
+            .await?;
+
+        Ok(codes)
+    }
+
+    pub async fn get_access_token_by_hash(
+        db: &PrismaClient,
+        token_hash: String,
+    ) -> Result<access_token_with_status::Data, DbErr> {
+        let token = db
+            .access_token()
+            .find_unique(access_token::token_hash::equals(token_hash))
+            .include(access_token_with_status::include())
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        Ok(token)
+    }
+
+    pub async fn get_access_tokens(
+        db: &PrismaClient,
+        user_id: String,
+    ) -> Result<Vec<access_token::Data>, DbErr> {
+        let tokens = db
+            .access_token()
+            .find_many(vec![access_token::user_id::equals(user_id)])
+            .order_by(access_token::created_at::order(
+                prisma_client_rust::Direction::Desc,
+            ))
+            .exec()
+            .await?;
+
+        Ok(tokens)
+    }
+
+    pub async fn get_identity(
+        db: &PrismaClient,
+        issuer: String,
+        subject: String,
+    ) -> Result<Option<identity_with_user::Data>, DbErr> {
+        let identity = db
+            .identity()
+            .find_first(vec![
+                identity::issuer::equals(issuer),
+                identity::subject::equals(subject),
+            ])
+            .include(identity_with_user::include())
+            .exec()
+            .await?;
+
+        Ok(identity)
+    }
+
+    // Four counts, without loading any of the related rows
+    pub async fn get_profile_stats(db: &PrismaClient, id: String) -> Result<ProfileStats, DbErr> {
+        #[derive(serde::Deserialize)]
+        struct Count {
+            count: i64,
+        }
+
+        let (followers_count, following_count, articles_count) = db
+            ._batch((
+                db.user()
+                    .count(vec![user::follows::some(vec![user::id::equals(id.clone())])]),
+                db.user()
+                    .count(vec![user::followers::some(vec![user::id::equals(id.clone())])]),
+                db.article().count(vec![
+                    article::user_id::equals(id.clone()),
+                    article::hidden_at::equals(None),
+                ]),
+            ))
+            .await?;
+
+        // Column "A" of the implicit favorites table is the article
+        let favorites_received = db
+            ._query_raw::<Count>(raw!(
+                r#"SELECT COUNT(*)::int AS count FROM "_UserFavorites" f
+                JOIN "Article" a ON a.id = f."A" WHERE a."userId" = {}"#,
+                PrismaValue::String(id)
+            ))
+            .exec()
+            .await?
+            .first()
+            .map(|x| x.count)
+            .unwrap_or(0);
+
+        Ok(ProfileStats {
+            followers_count,
+            following_count,
+            articles_count,
+            favorites_received,
+        })
+    }
+
+    pub async fn get_followers(
+        db: &PrismaClient,
+        user_id: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserData>, DbErr> {
+        let users = db
+            .user()
+            .find_many(vec![
+                user::follows::some(vec![user::id::equals(user_id)]),
+                active_user(),
+            ])
+            .order_by(user::username::order(prisma_client_rust::Direction::Asc))
+            .skip(offset)
+            .take(limit)
+            .exec()
+            .await?;
+
+        Ok(users)
+    }
+
+    pub async fn get_following(
+        db: &PrismaClient,
+        user_id: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserData>, DbErr> {
+        let users = db
+            .user()
+            .find_many(vec![
+                user::followers::some(vec![user::id::equals(user_id)]),
+                active_user(),
+            ])
+            .order_by(user::username::order(prisma_client_rust::Direction::Asc))
+            .skip(offset)
+            .take(limit)
+            .exec()
+            .await?;
+
+        Ok(users)
+    }
+
+    pub async fn is_blocked(
+        db: &PrismaClient,
+        blocker_id: String,
+        blocked_id: String,
+    ) -> Result<bool, DbErr> {
+        let count = db
+            .user()
+            .count(vec![
+                user::id::equals(blocker_id),
+                user::blocking::some(vec![user::id::equals(blocked_id)]),
+            ])
+            .exec()
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    pub async fn get_blocked_users(
+        db: &PrismaClient,
+        user_id: String,
+    ) -> Result<Vec<UserData>, DbErr> {
+        let users = db
+            .user()
+            .find_many(vec![user::blocked_by::some(vec![user::id::equals(user_id)])])
+            .exec()
+            .await?;
+
+        Ok(users)
+    }
+
+    pub async fn get_muted_users(
+        db: &PrismaClient,
+        user_id: String,
+    ) -> Result<Vec<UserData>, DbErr> {
+        let users = db
+            .user()
+            .find_many(vec![user::muted_by::some(vec![user::id::equals(user_id)])])
+            .exec()
+            .await?;
+
+        Ok(users)
+    }
+
+    pub async fn get_user_status(
+        db: &PrismaClient,
+        id: String,
+    ) -> Result<user_status::Data, DbErr> {
+        let user = db
+            .user()
+            .find_unique(user::id::equals(id))
+            .select(user_status::select())
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        Ok(user)
+    }
+
+    pub async fn search_users(
+        db: &PrismaClient,
+        q: Option<String>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<UserData>, i64), DbErr> {
+        // Where params can't be cloned, so they are built once per query
+        let filters = || -> Vec<user::WhereParam> {
+            match &q {
+                Some(q) => vec![or(vec![
+                    user::username::contains(q.clone()),
+                    user::email::contains(q.clone()),
+                ])],
+                None => vec![],
+            }
+        };
+
+        let (users, count) = db
+            ._batch((
+                db.user()
+                    .find_many(filters())
+                    .order_by(user::created_at::order(
+                        prisma_client_rust::Direction::Desc,
+                    ))
+                    .skip(offset)
+                    .take(limit),
+                db.user().count(filters()),
+            ))
+            .await?;
+
+        Ok((users, count))
+    }
+
+    // Usernames starting with `q` come first, then usernames holding its letters
+    // in order and bios containing it, each ranked by follower count. Users who
+    // blocked the viewer are left out, as their profiles are hidden from them.
+    // Column "A" of the implicit follows table is the followed user, and of the
+    // blocks table the blocked one.
+    pub async fn search_profiles(
+        db: &PrismaClient,
+        q: &str,
+        viewer: Option<String>,
+        prefix_only: bool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<UserData>, i64), DbErr> {
+        #[derive(serde::Deserialize)]
+        struct Match {
+            id: String,
+            total: i64,
+        }
+
+        let escaped = escape_like(q);
+        let prefix = format!("{escaped}%");
+        let fuzzy = format!(
+            "%{}%",
+            q.chars().map(|x| escape_like(&x.to_string())).collect::<Vec<_>>().join("%")
+        );
+        let contains = format!("%{escaped}%");
+
+        let matches = db
+            ._query_raw::<Match>(raw!(
+                r#"WITH matches AS (
+                    SELECT u.id, u.username, u.username ILIKE {} AS prefix,
+                        (SELECT COUNT(*) FROM "_follows" f WHERE f."A" = u.id) AS followers
+                    FROM "User" u
+                    WHERE (u.username ILIKE {}
+                        OR (NOT {} AND (u.username ILIKE {} OR u.bio ILIKE {})))
+                    AND (u."suspendedAt" IS NULL OR u."suspendedUntil" < now())
+                    AND NOT EXISTS (
+                        SELECT 1 FROM "_blocks" b WHERE b."A" = {} AND b."B" = u.id
+                    )
+                )
+                SELECT id, (COUNT(*) OVER ())::int AS total FROM matches
+                ORDER BY prefix DESC, followers DESC, username
+                LIMIT {} OFFSET {}"#,
+                PrismaValue::String(prefix.clone()),
+                PrismaValue::String(prefix),
+                PrismaValue::Boolean(prefix_only),
+                PrismaValue::String(fuzzy),
+                PrismaValue::String(contains),
+                PrismaValue::String(viewer.unwrap_or_default()),
+                PrismaValue::Int(limit),
+                PrismaValue::Int(offset)
+            ))
+            .exec()
+            .await?;
+
+        let total = matches.first().map(|x| x.total).unwrap_or(0);
+        let ids: Vec<String> = matches.into_iter().map(|x| x.id).collect();
+
+        let mut users = db
+            .user()
+            .find_many(vec![user::id::in_vec(ids.clone())])
+            .exec()
+            .await?;
+        users.sort_by_key(|x| ids.iter().position(|id| *id == x.id));
+
+        Ok((users, total))
+    }
+
+    pub async fn get_report(
+        db: &PrismaClient,
+        id: String,
+    ) -> Result<report_with_content::Data, DbErr> {
+        let report = db
+            .report()
+            .find_unique(report::id::equals(id))
+            .include(report_with_content::include())
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        Ok(report)
+    }
+
+    pub async fn get_reports(
+        db: &PrismaClient,
+        status: Option<ReportStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<report_with_content::Data>, i64), DbErr> {
+        let filters = || -> Vec<report::WhereParam> {
+            status
+                .map(|x| vec![report::status::equals(x)])
+                .unwrap_or_default()
+        };
+
+        // Oldest first, so the queue is worked through in order
+        let (reports, count) = db
+            ._batch((
+                db.report()
+                    .find_many(filters())
+                    .order_by(report::created_at::order(
+                        prisma_client_rust::Direction::Asc,
+                    ))
+                    .skip(offset)
+                    .take(limit)
+                    .include(report_with_content::include()),
+                db.report().count(filters()),
+            ))
+            .await?;
+
+        Ok((reports, count))
+    }
+
+    pub async fn get_notifications(
+        db: &PrismaClient,
+        user_id: String,
+        unread_only: bool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<notification_with_actor::Data>, i64), DbErr> {
+        let mut filters = vec![notification::recipient_id::equals(user_id.clone())];
+        if unread_only {
+            filters.push(notification::read_at::equals(None));
+        }
+
+        let (notifications, unread_count) = db
+            ._batch((
+                db.notification()
+                    .find_many(filters)
+                    .order_by(notification::created_at::order(
+                        prisma_client_rust::Direction::Desc,
+                    ))
+                    .skip(offset)
+                    .take(limit)
+                    .include(notification_with_actor::include()),
+                db.notification().count(vec![
+                    notification::recipient_id::equals(user_id),
+                    notification::read_at::equals(None),
+                ]),
+            ))
+            .await?;
+
+        Ok((notifications, unread_count))
+    }
+
+    pub async fn get_muted_notifications(
+        db: &PrismaClient,
+        user_id: String,
+    ) -> Result<Vec<NotificationKind>, DbErr> {
+        let user = db
+            .user()
+            .find_unique(user::id::equals(user_id))
+            .select(user::select!({ muted_notifications }))
+            .exec()
+            .await?
+            .ok_or(DbErr::NotFound)?;
+
+        Ok(user.muted_notifications)
+    }
+
+    pub async fn get_audit_logs(
+        db: &PrismaClient,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<audit_log::Data>, DbErr> {
+        let logs = db
+            .audit_log()
+            .find_many(vec![])
+            .order_by(audit_log::created_at::order(
+                prisma_client_rust::Direction::Desc,
+            ))
+            .skip(offset)
+            .take(limit)
+            .exec()
+            .await?;
+
+        Ok(logs)
     }
 }
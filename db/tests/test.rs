@@ -1,9 +1,10 @@
 use std::sync::Arc;
 
-use db::{mutation::{Mutation, article_with_user}, prisma::PrismaClient};
+use db::{mutation::{Mutation, article_with_user}, prisma::PrismaClient, query::Query};
+use prisma_client_rust::chrono::Duration;
 use fake::{Fake, Faker};
 use tokio::sync::OnceCell;
-use types::{article::NewArticle, user::NewUserRequest};
+use types::{article::{NewArticle, Params}, user::NewUserRequest};
 
 static CLIENT: OnceCell<Arc<PrismaClient>> = OnceCell::const_new();
 
@@ -26,7 +27,7 @@ async fn get_client() -> &'static Arc<PrismaClient> {
 async fn new_user() -> (db::prisma::user::Data, NewUserRequest) {
     let client = get_client().await;
     let user_input: NewUserRequest = Faker.fake();
-    let user = Mutation::create_user(client, user_input.clone())
+    let user = Mutation::create_user(client, user_input.clone(), Duration::days(30))
         .await
         .expect("Couldn't create user");
     (user, user_input)
@@ -36,7 +37,7 @@ async fn new_article() -> (article_with_user::Data, NewArticle) {
     let client = get_client().await;
     let article_input: NewArticle = Faker.fake();
     let (user, _) = new_user().await;
-    let article = Mutation::create_article(client, article_input.clone(), user.id)
+    let (article, _) = Mutation::create_article(client, article_input.clone(), user.id)
         .await
         .expect("Couldn't create article");
     (article, article_input)
@@ -55,4 +56,121 @@ async fn create_article() {
 
     assert_eq!(slug::slugify(&input.article.title), article.slug);
     assert_eq!(input.article.tag_list, article.tag_list);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn moderator_delete_article() {
+    let client = get_client().await;
+    let (article, _) = new_article().await;
+    let (moderator, _) = new_user().await;
+
+    let res = Mutation::delete_article(client, article.slug.clone(), moderator.id.clone(), false).await;
+    assert!(matches!(res, Err(db::DbErr::Unauthorized)));
+
+    Mutation::delete_article(client, article.slug, moderator.id.clone(), true)
+        .await
+        .expect("Moderator couldn't delete article");
+
+    let logs = db::query::Query::get_audit_logs(client, 100, 0).await.unwrap();
+    assert!(logs
+        .iter()
+        .any(|x| x.actor_id == moderator.id && x.target_id == article.id));
+}
+
+#[test]
+fn parse_mentions() {
+    let handles = db::mutation::parse_mentions(
+        "Thanks @alice and @bob_2! Mail me at carol@example.com, or ask @alice.",
+    );
+    assert_eq!(handles, vec!["alice", "bob_2"]);
+}
+
+fn feed_params(limit: i64) -> Params {
+    Params { tag: None, author: None, favorited: None, limit: Some(limit), offset: None }
+}
+
+#[tokio::test]
+async fn feed_without_follows() {
+    let client = get_client().await;
+    let (reader, _) = new_user().await;
+    new_article().await;
+
+    let feed = Query::get_followed_articles(client, reader.id, feed_params(20))
+        .await
+        .unwrap();
+    assert!(feed.is_empty());
+}
+
+#[tokio::test]
+async fn feed_with_many_follows() {
+    let client = get_client().await;
+    let (reader, _) = new_user().await;
+
+    let mut slugs = vec![];
+    for _ in 0..50 {
+        let (article, _) = new_article().await;
+        Mutation::follow_unfollow_user(client, reader.id.clone(), article.user.id.clone(), true)
+            .await
+            .unwrap();
+        slugs.push(article.slug);
+    }
+    // Someone the reader doesn't follow
+    new_article().await;
+
+    let feed = Query::get_followed_articles(client, reader.id, feed_params(100))
+        .await
+        .unwrap();
+    assert_eq!(feed.len(), slugs.len());
+    assert!(feed.iter().all(|x| slugs.contains(&x.slug)));
+    assert!(feed.windows(2).all(|x| x[0].created_at >= x[1].created_at));
+}
+
+#[tokio::test]
+async fn article_counters() {
+    let client = get_client().await;
+    let (article, _) = new_article().await;
+    let (reader, _) = new_user().await;
+
+    // Favoriting twice only counts once
+    for _ in 0..2 {
+        Mutation::favorite_unfavorite_article(client, article.slug.clone(), reader.id.clone(), true)
+            .await
+            .unwrap();
+    }
+    let (comment, _) = Mutation::create_comment(
+        client,
+        Faker.fake(),
+        article.slug.clone(),
+        reader.id.clone(),
+    )
+    .await
+    .unwrap();
+
+    let stored = Query::get_article_by_slug(client, article.slug.clone()).await.unwrap();
+    assert_eq!(stored.favorites_count, 1);
+    assert_eq!(stored.comments_count, 1);
+
+    Mutation::delete_comment(client, comment.id, reader.id.clone(), false)
+        .await
+        .unwrap();
+    let (stored, _) =
+        Mutation::favorite_unfavorite_article(client, article.slug.clone(), reader.id, false)
+            .await
+            .unwrap();
+    assert_eq!(stored.favorites_count, 0);
+    assert_eq!(stored.comments_count, 0);
+
+    client
+        .article()
+        .update(
+            db::prisma::article::id::equals(article.id),
+            vec![db::prisma::article::favorites_count::set(7)],
+        )
+        .exec()
+        .await
+        .unwrap();
+    assert!(Mutation::reconcile_counters(client).await.unwrap() >= 1);
+
+    let stored = Query::get_article_by_slug(client, article.slug).await.unwrap();
+    assert_eq!(stored.favorites_count, 0);
+}
@@ -16,7 +16,7 @@ pub fn article(_props: &ArticleProps) -> Html {
                 <h1>{&_props.article.title}</h1>
 
                 <div class="article-meta">
-                    <a href=""><img alt="profile pic" src={_props.article.author.profile.image.as_ref().unwrap().to_owned()}/></a>
+                    <a href=""><img alt="profile pic" src={_props.article.author.profile.image.clone().unwrap_or_default()}/></a>
                     <div class="info">
                         <a href="" class="author">{&_props.article.author.profile.username}</a>
                         <span class="date">{&_props.article.created_at.to_string()}</span>
@@ -54,7 +54,7 @@ pub fn article(_props: &ArticleProps) -> Html {
 
             <div class="article-actions">
             <div class="article-meta">
-                <a href=""><img alt="profile pic" src={_props.article.author.profile.image.as_ref().unwrap().to_owned()}/></a>
+                <a href=""><img alt="profile pic" src={_props.article.author.profile.image.clone().unwrap_or_default()}/></a>
                 <div class="info">
                     <a href="" class="author">{&_props.article.author.profile.username}</a>
                     <span class="date">{&_props.article.created_at.to_string()}</span>
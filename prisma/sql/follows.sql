@@ -0,0 +1,42 @@
+-- Follows used to be stored by pointing the followed user's "userId" at the
+-- follower, which left room for a single follower per user. They now live in
+-- the "_follows" join table (A is the followed user, B the follower).
+--
+-- Run this before `cargo prisma db push`, which would otherwise drop "userId"
+-- and every follow with it:
+--
+--   cargo prisma db execute --file prisma/sql/follows.sql --schema prisma/schema.prisma
+--
+-- It creates the join table the way `db push` would, copies the old follows
+-- over and drops the old column. Running it again does nothing.
+DO $$
+BEGIN
+    -- A fresh database, `db push` creates everything
+    IF to_regclass('"User"') IS NULL THEN
+        RETURN;
+    END IF;
+
+    CREATE TABLE IF NOT EXISTS "_follows" ("A" TEXT NOT NULL, "B" TEXT NOT NULL);
+    CREATE UNIQUE INDEX IF NOT EXISTS "_follows_AB_unique" ON "_follows"("A", "B");
+    CREATE INDEX IF NOT EXISTS "_follows_B_index" ON "_follows"("B");
+
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = '_follows_A_fkey') THEN
+        ALTER TABLE "_follows" ADD CONSTRAINT "_follows_A_fkey"
+            FOREIGN KEY ("A") REFERENCES "User"("id") ON DELETE CASCADE ON UPDATE CASCADE;
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = '_follows_B_fkey') THEN
+        ALTER TABLE "_follows" ADD CONSTRAINT "_follows_B_fkey"
+            FOREIGN KEY ("B") REFERENCES "User"("id") ON DELETE CASCADE ON UPDATE CASCADE;
+    END IF;
+
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'User' AND column_name = 'userId'
+    ) THEN
+        INSERT INTO "_follows" ("A", "B")
+        SELECT id, "userId" FROM "User" WHERE "userId" IS NOT NULL
+        ON CONFLICT DO NOTHING;
+
+        ALTER TABLE "User" DROP COLUMN "userId";
+    END IF;
+END $$;
@@ -0,0 +1,113 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Serialize, Deserialize};
+
+use crate::user::Role;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLogEntry {
+    pub id: String,
+    #[serde(rename = "actorId")]
+    pub actor_id: String,
+    pub action: String,
+    #[serde(rename = "targetType")]
+    pub target_type: String,
+    #[serde(rename = "targetId")]
+    pub target_id: String,
+    pub details: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLog {
+    pub entries: Vec<AuditLogEntry>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearchParams {
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminUser {
+    pub user: AdminUserBody
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminUsers {
+    pub users: Vec<AdminUserBody>,
+    #[serde(rename = "usersCount")]
+    pub users_count: i64
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminUserBody {
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    pub role: Role,
+    #[serde(rename = "emailVerified")]
+    pub email_verified: bool,
+    #[serde(rename = "passwordResetRequired")]
+    pub password_reset_required: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+    pub suspension: Option<SuspensionBody>
+}
+
+/// A suspension without `expiresAt` is a ban
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SuspensionBody {
+    pub reason: String,
+    #[serde(rename = "suspendedAt")]
+    pub suspended_at: DateTime<FixedOffset>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<FixedOffset>>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Suspend {
+    pub suspension: SuspendBody
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SuspendBody {
+    pub reason: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<FixedOffset>>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Impersonation {
+    pub impersonation: ImpersonationBody
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImpersonationBody {
+    pub username: String,
+    pub token: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<FixedOffset>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub entries: i64,
+    pub capacity: i64,
+    pub hits: i64,
+    pub misses: i64
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cache {
+    pub cache: CacheStats
+}
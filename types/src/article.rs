@@ -6,7 +6,10 @@ use fake::{Dummy, Fake};
 use fake::faker::lorem::en::{Sentence, Words};
 use yew_macro::Properties;
 
-use crate::user::Profile;
+use crate::{
+    patch::Patch,
+    user::{Mention, Profile},
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Article {
@@ -28,7 +31,13 @@ pub struct ArticleBody {
     pub favorited: bool,
     #[serde(rename = "favoritesCount")]
     pub favorites_count: i32,
-    pub author: Profile
+    #[serde(rename = "commentsCount", default)]
+    pub comments_count: i32,
+    #[serde(default)]
+    pub version: i32,
+    pub author: Profile,
+    #[serde(default)]
+    pub mentions: Vec<Mention>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -70,11 +79,24 @@ pub struct UpdateArticle {
 #[cfg_attr(feature = "fake", derive(Dummy))]
 pub struct UpdateArticleBody {
     #[cfg_attr(feature = "fake", dummy(faker = "Sentence(1..3)"))]
-    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub title: Patch<String>,
     #[cfg_attr(feature = "fake", dummy(faker = "Sentence(1..4)"))]
-    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub description: Patch<String>,
     #[cfg_attr(feature = "fake", dummy(faker = "Sentence(5..8)"))]
-    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub body: Patch<String>,
+    /// The version being edited, the update fails if the article moved on since
+    #[cfg_attr(feature = "fake", dummy(default))]
+    pub version: Option<i32>,
+}
+
+impl UpdateArticleBody {
+    /// Every field of an article is required, so none of them can be cleared
+    pub fn clears_required(&self) -> bool {
+        self.title.is_null() || self.description.is_null() || self.body.is_null()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
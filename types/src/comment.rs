@@ -5,7 +5,7 @@ use fake::{Dummy, Fake};
 #[cfg(feature = "fake")]
 use fake::faker::lorem::en::Sentence;
 
-use crate::user::Profile;
+use crate::user::{Mention, Profile};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CommentBody {
@@ -15,7 +15,9 @@ pub struct CommentBody {
     pub created_at: DateTime<FixedOffset>,
     #[serde(rename = "updatedAt")]
     pub updated_at: DateTime<FixedOffset>,
-    pub author: Profile
+    pub author: Profile,
+    #[serde(default)]
+    pub mentions: Vec<Mention>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -1,3 +1,8 @@
 pub mod user;
 pub mod article;
-pub mod comment;
\ No newline at end of file
+pub mod comment;
+pub mod token;
+pub mod admin;
+pub mod report;
+pub mod notification;
+pub mod patch;
\ No newline at end of file
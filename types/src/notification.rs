@@ -0,0 +1,52 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    Follow,
+    Favorite,
+    Comment,
+    Mention,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notifications {
+    pub notifications: Vec<NotificationBody>,
+    #[serde(rename = "unreadCount")]
+    pub unread_count: i64
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationBody {
+    pub id: String,
+    pub kind: NotificationKind,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+    pub read: bool,
+    /// Username of whoever triggered the notification
+    pub actor: String,
+    /// Slug of the article it's about, if any
+    pub article: Option<String>,
+    pub comment: Option<String>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationParams {
+    /// Only return unread notifications
+    #[serde(default)]
+    pub unread: bool,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationSettings {
+    pub settings: NotificationSettingsBody
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationSettingsBody {
+    /// Kinds of notifications the user doesn't want to get
+    pub muted: Vec<NotificationKind>
+}
@@ -0,0 +1,97 @@
+#[cfg(feature = "fake")]
+use fake::Dummy;
+#[cfg(feature = "fake")]
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A field of an update payload. Leaving it out keeps the current value,
+/// `null` clears it and anything else replaces it.
+///
+/// Use it with `#[serde(default, skip_serializing_if = "Patch::is_absent")]`,
+/// so that a missing field stays apart from an explicit `null`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Patch<T> {
+    #[default]
+    Absent,
+    Null,
+    Value(T),
+}
+
+impl<T> Patch<T> {
+    pub fn is_absent(&self) -> bool {
+        matches!(self, Patch::Absent)
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Patch::Null)
+    }
+
+    pub fn as_ref(&self) -> Patch<&T> {
+        match self {
+            Patch::Absent => Patch::Absent,
+            Patch::Null => Patch::Null,
+            Patch::Value(x) => Patch::Value(x),
+        }
+    }
+
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Patch<U> {
+        match self {
+            Patch::Absent => Patch::Absent,
+            Patch::Null => Patch::Null,
+            Patch::Value(x) => Patch::Value(f(x)),
+        }
+    }
+
+    /// The new value, if there is one
+    pub fn value(self) -> Option<T> {
+        match self {
+            Patch::Value(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// `None` when the field stays as it is, `Some(None)` when it's cleared
+    pub fn into_option(self) -> Option<Option<T>> {
+        match self {
+            Patch::Absent => None,
+            Patch::Null => Some(None),
+            Patch::Value(x) => Some(Some(x)),
+        }
+    }
+}
+
+impl<T> From<Option<T>> for Patch<T> {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(x) => Patch::Value(x),
+            None => Patch::Null,
+        }
+    }
+}
+
+// Only called for fields that are present, missing ones fall back to `Absent`
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Patch<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Option::deserialize(deserializer).map(Patch::from)
+    }
+}
+
+impl<T: Serialize> Serialize for Patch<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Patch::Value(x) => serializer.serialize_some(x),
+            _ => serializer.serialize_none(),
+        }
+    }
+}
+
+#[cfg(feature = "fake")]
+impl<T: Dummy<F>, F> Dummy<F> for Patch<T> {
+    fn dummy_with_rng<R: Rng + ?Sized>(config: &F, rng: &mut R) -> Self {
+        if rng.gen_bool(0.5) {
+            Patch::Value(T::dummy_with_rng(config, rng))
+        } else {
+            Patch::Absent
+        }
+    }
+}
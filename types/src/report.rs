@@ -0,0 +1,82 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    Open,
+    Dismissed,
+    Resolved,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportAction {
+    /// Closes the reports and shows the content again if it was auto-hidden
+    Dismiss,
+    Hide,
+    /// Hides the content and suspends its author
+    Suspend,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewReport {
+    pub report: NewReportBody
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewReportBody {
+    pub reason: String
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Report {
+    pub report: ReportBody
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Reports {
+    pub reports: Vec<ReportBody>,
+    #[serde(rename = "reportsCount")]
+    pub reports_count: i64
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportBody {
+    pub id: String,
+    pub reason: String,
+    pub status: ReportStatus,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+    /// Username of whoever sent the report
+    pub reporter: String,
+    /// Username of the reported content's author
+    pub author: String,
+    /// Slug of the reported article, or of the article the comment is on
+    pub article: String,
+    pub comment: Option<String>,
+    #[serde(rename = "contentHidden")]
+    pub content_hidden: bool
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportParams {
+    pub status: Option<ReportStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportResolution {
+    pub resolution: ReportResolutionBody
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportResolutionBody {
+    pub action: ReportAction,
+    /// Suspension reason, defaults to the report's reason
+    pub reason: Option<String>,
+    /// Suspension end, leaving it out bans the author
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<FixedOffset>>
+}
@@ -0,0 +1,98 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Serialize, Deserialize};
+
+/// What a personal access token is allowed to do.
+/// Regular sessions aren't limited by scopes.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "read")]
+    Read,
+    #[serde(rename = "articles:write")]
+    ArticlesWrite,
+    #[serde(rename = "comments:write")]
+    CommentsWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::ArticlesWrite => "articles:write",
+            Scope::CommentsWrite => "comments:write",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "articles:write" => Ok(Scope::ArticlesWrite),
+            "comments:write" => Ok(Scope::CommentsWrite),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NewAccessToken {
+    pub token: NewAccessTokenBody
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NewAccessTokenBody {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessToken {
+    pub token: AccessTokenBody
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessTokenBody {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<FixedOffset>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+    /// Only returned once, when the token is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessTokens {
+    pub tokens: Vec<AccessTokenBody>
+}
+
+/// Lets a client that can't set headers, like the browser's `EventSource`,
+/// open the event stream as `/api/stream?ticket=...`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamTicket {
+    pub ticket: StreamTicketBody
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamTicketBody {
+    pub token: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<FixedOffset>,
+}
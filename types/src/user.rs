@@ -4,6 +4,48 @@ use fake::{Dummy, Fake};
 use fake::faker::internet::en::{Username, Password, FreeEmail};
 use serde::{Serialize, Deserialize};
 
+use crate::patch::Patch;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    /// Moderators and admins can edit or delete anybody's content
+    pub fn can_moderate(&self) -> bool {
+        matches!(self, Role::Moderator | Role::Admin)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Profiles {
+    pub profiles: Vec<ProfileBody>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProfileSearch {
+    pub profiles: Vec<ProfileBody>,
+    #[serde(rename = "profilesCount")]
+    pub profiles_count: i64
+}
+
+/// What a mention picker needs to show a user
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Suggestion {
+    pub username: String,
+    pub image: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Suggestions {
+    pub suggestions: Vec<Suggestion>
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Profile {
     pub profile: ProfileBody 
@@ -14,7 +56,29 @@ pub struct ProfileBody {
     pub username: String,
     pub bio: String,
     pub image: Option<String>,
-    pub following: bool
+    pub following: bool,
+    /// Only filled in when a single profile is requested
+    #[serde(flatten)]
+    pub stats: Option<ProfileStats>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProfileStats {
+    #[serde(rename = "followersCount")]
+    pub followers_count: i64,
+    #[serde(rename = "followingCount")]
+    pub following_count: i64,
+    #[serde(rename = "articlesCount")]
+    pub articles_count: i64,
+    #[serde(rename = "favoritesReceived")]
+    pub favorites_received: i64
+}
+
+/// `handle` is the name as written in the body, `username` the user's current one
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Mention {
+    pub handle: String,
+    pub username: String,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -46,6 +110,11 @@ pub struct UserBody {
     pub username: String,
     pub bio: String,
     pub image: Option<String>,
+    #[serde(rename = "emailVerified")]
+    pub email_verified: bool,
+    pub role: Role,
+    #[serde(default)]
+    pub version: i32,
 }
 
 #[derive(serde::Deserialize)]
@@ -65,10 +134,133 @@ pub struct UpdateUser {
 }
 
 #[derive(Deserialize, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "fake", derive(Dummy))]
 pub struct UpdateUserBody {
-    pub email: Option<String>,
-    pub username: Option<String>,
-    pub password: Option<String>,
-    pub image: Option<String>,
-    pub bio: Option<String>,
-}
\ No newline at end of file
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub email: Patch<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub username: Patch<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub password: Patch<String>,
+    /// `null` removes the avatar
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub image: Patch<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_absent")]
+    pub bio: Patch<String>,
+    /// The version being edited, the update fails if the profile moved on since
+    #[cfg_attr(feature = "fake", dummy(default))]
+    pub version: Option<i32>,
+}
+
+impl UpdateUserBody {
+    /// Email, username and password can't be cleared, only changed
+    pub fn clears_required(&self) -> bool {
+        self.email.is_null() || self.username.is_null() || self.password.is_null()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PasswordResetRequest {
+    pub user: PasswordResetRequestBody
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PasswordResetRequestBody {
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PasswordResetConfirm {
+    pub user: PasswordResetConfirmBody
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PasswordResetConfirmBody {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyEmail {
+    pub user: VerifyEmailBody
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyEmailBody {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoginChallenge {
+    #[serde(rename = "twoFactor")]
+    pub two_factor: LoginChallengeBody
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoginChallengeBody {
+    #[serde(rename = "challengeToken")]
+    pub challenge_token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TwoFactorLogin {
+    pub user: TwoFactorLoginBody
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TwoFactorLoginBody {
+    #[serde(rename = "challengeToken")]
+    pub challenge_token: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorEnrollment {
+    #[serde(rename = "twoFactor")]
+    pub two_factor: TwoFactorEnrollmentBody
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorEnrollmentBody {
+    pub secret: String,
+    #[serde(rename = "otpauthUri")]
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TwoFactorCode {
+    pub user: TwoFactorCodeBody
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TwoFactorCodeBody {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecoveryCodes {
+    #[serde(rename = "recoveryCodes")]
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OidcAuthorization {
+    #[serde(rename = "authorizationUrl")]
+    pub authorization_url: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OidcCallback {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UpdateRole {
+    pub user: UpdateRoleBody
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UpdateRoleBody {
+    pub role: Role,
+}